@@ -0,0 +1,215 @@
+use std::{env, fs::File, io::BufWriter, path::{Path, PathBuf}, process};
+
+use rust_gbc::hardware::{GameBoy, GameBoyEvent, bus::Bus, apu::NATIVE_SAMPLE_RATE, audio::{Resampler, MIN_SAMPLE_RATE}, wav::WavWriter, SCREEN_WIDTH, SCREEN_HEIGHT};
+
+const USAGE: &str = "Uso: headless <rom> [--frames N] [--wav salida.wav] [--stems] [--rate HZ] [--screenshot salida.png] [--expect referencia.png]";
+
+const DEFAULT_FRAMES: u32 = 60 * 60;
+const DEFAULT_SAMPLE_RATE: u32 = 44100;
+
+struct Options {
+    rom: String,
+    frames: u32,
+    wav: Option<PathBuf>,
+    // Un fichero mas por canal: salida.ch1.wav ... salida.ch4.wav
+    stems: bool,
+    sample_rate: u32,
+    // Ultimo frame en PNG
+    screenshot: Option<PathBuf>,
+    // Imagen con la que se compara el ultimo frame, por ejemplo la de dmg-acid2
+    expect: Option<PathBuf>,
+}
+
+fn parse_args() -> Result<Options, String> {
+    let mut args = env::args().skip(1);
+
+    let mut rom = None;
+    let mut frames = DEFAULT_FRAMES;
+    let mut wav = None;
+    let mut stems = false;
+    let mut sample_rate = DEFAULT_SAMPLE_RATE;
+    let mut screenshot = None;
+    let mut expect = None;
+
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--frames" => frames = parse_value(&arg, args.next())?,
+            "--rate" => sample_rate = parse_value(&arg, args.next())?,
+            "--wav" => wav = Some(PathBuf::from(args.next().ok_or("Falta el fichero de --wav")?)),
+            "--stems" => stems = true,
+            "--screenshot" => screenshot = Some(PathBuf::from(args.next().ok_or("Falta el fichero de --screenshot")?)),
+            "--expect" => expect = Some(PathBuf::from(args.next().ok_or("Falta el fichero de --expect")?)),
+            _ if arg.starts_with("--") => return Err(format!("Opcion desconocida: {}", arg)),
+            _ if rom.is_none() => rom = Some(arg),
+            _ => return Err(format!("Argumento de mas: {}", arg)),
+        }
+    }
+
+    if stems && wav.is_none() {
+        return Err("--stems necesita --wav".to_string());
+    }
+    if !(MIN_SAMPLE_RATE..=NATIVE_SAMPLE_RATE).contains(&sample_rate) {
+        return Err(format!("Frecuencia no valida: {}", sample_rate));
+    }
+
+    Ok(Options {
+        rom: rom.ok_or("Falta la ROM")?,
+        frames,
+        wav,
+        stems,
+        sample_rate,
+        screenshot,
+        expect,
+    })
+}
+
+fn parse_value(option: &str, value: Option<String>) -> Result<u32, String> {
+    let value = value.ok_or(format!("Falta el valor de {}", option))?;
+    value.parse().map_err(|_| format!("Valor no valido para {}: {}", option, value))
+}
+
+// Fichero WAV con su conversion desde la frecuencia del APU
+struct Recording {
+    wav: WavWriter<std::io::BufWriter<std::fs::File>>,
+    resampler: Resampler,
+    buffer: Vec<f32>,
+}
+
+impl Recording {
+    fn create(path: &PathBuf, sample_rate: u32) -> Result<Self, String> {
+        // La cabecera lleva la frecuencia que genera de verdad el resampler
+        let resampler = Resampler::new(NATIVE_SAMPLE_RATE, sample_rate);
+
+        Ok(Recording {
+            wav: WavWriter::create(path, resampler.output_rate()).map_err(|e| format!("No se ha podido crear {}: {}", path.display(), e))?,
+            resampler,
+            buffer: Vec::new(),
+        })
+    }
+
+    fn write(&mut self, samples: &[f32]) -> Result<(), String> {
+        self.buffer.clear();
+        self.resampler.process(samples, 1.0, &mut self.buffer);
+        self.wav.write_samples(&self.buffer).map_err(|e| format!("Error al escribir el WAV: {}", e))
+    }
+
+    fn finish(&mut self) -> Result<(), String> {
+        self.wav.finish().map_err(|e| format!("Error al cerrar el WAV: {}", e))
+    }
+}
+
+fn run(options: Options) -> Result<(), String> {
+    let mut gameboy = GameBoy::new(Bus::new(), true);
+    gameboy.reset();
+    gameboy.load_rom(&options.rom).map_err(|e| format!("No se ha podido cargar {}: {}", options.rom, e))?;
+    gameboy.set_audio_sample_rate(NATIVE_SAMPLE_RATE);
+    gameboy.set_audio_stems(options.stems);
+
+    let mut mix = match options.wav.as_ref() {
+        Some(path) => Some(Recording::create(path, options.sample_rate)?),
+        None => None,
+    };
+
+    let mut stems = Vec::new();
+    if options.stems {
+        if let Some(path) = options.wav.as_ref() {
+            for channel in 1..=4 {
+                let stem_path = path.with_extension(format!("ch{}.wav", channel));
+                stems.push(Recording::create(&stem_path, options.sample_rate)?);
+            }
+        }
+    }
+
+    for _ in 0..options.frames {
+        gameboy.cycle();
+
+        while let Some(event) = gameboy.poll_event() {
+            if let GameBoyEvent::SaveFailed(e) = event {
+                eprintln!("No se ha podido guardar la partida: {}", e);
+            }
+        }
+
+        let samples = gameboy.take_audio_samples();
+        if let Some(mix) = mix.as_mut() {
+            mix.write(&samples)?;
+        }
+
+        if let Some(channels) = gameboy.take_audio_stems() {
+            for (stem, samples) in stems.iter_mut().zip(channels) {
+                stem.write(&samples)?;
+            }
+        }
+    }
+
+    for recording in mix.iter_mut().chain(stems.iter_mut()) {
+        recording.finish()?;
+    }
+
+    gameboy.save_ram().map_err(|e| format!("No se ha podido guardar la partida: {}", e))?;
+
+    if let Some(path) = options.screenshot.as_ref() {
+        write_screenshot(path, gameboy.frame_buffer()).map_err(|e| format!("No se ha podido guardar {}: {}", path.display(), e))?;
+    }
+
+    if let Some(path) = options.expect.as_ref() {
+        let expected = read_shades(path)?;
+        let frame: Vec<u8> = gameboy.frame_buffer().chunks(4).map(|px| shade(px[0], px[1], px[2])).collect();
+
+        let diff = frame.iter().zip(&expected).filter(|(a, b)| a != b).count();
+        if diff > 0 {
+            return Err(format!("El frame no coincide con {}: {} pixeles distintos", path.display(), diff));
+        }
+        println!("El frame coincide con {}", path.display());
+    }
+
+    Ok(())
+}
+
+fn write_screenshot(path: &Path, frame: &[u8]) -> Result<(), png::EncodingError> {
+    let mut encoder = png::Encoder::new(BufWriter::new(File::create(path)?), SCREEN_WIDTH as u32, SCREEN_HEIGHT as u32);
+    encoder.set_color(png::ColorType::Rgba);
+    encoder.set_depth(png::BitDepth::Eight);
+
+    let mut writer = encoder.write_header()?;
+    writer.write_image_data(frame)?;
+    writer.finish()
+}
+
+// Tono 0 (negro) - 3 (blanco) de un pixel. Se comparan tonos y no colores para que no importe la paleta
+fn shade(r: u8, g: u8, b: u8) -> u8 {
+    let luma = (r as u32 * 299 + g as u32 * 587 + b as u32 * 114) / 1000;
+    ((luma * 3 + 127) / 255) as u8
+}
+
+fn read_shades(path: &Path) -> Result<Vec<u8>, String> {
+    let error = |e: png::DecodingError| format!("No se ha podido leer {}: {}", path.display(), e);
+
+    let mut decoder = png::Decoder::new(File::open(path).map_err(|e| error(e.into()))?);
+    decoder.set_transformations(png::Transformations::normalize_to_color8());
+
+    let mut reader = decoder.read_info().map_err(error)?;
+    let mut buffer = vec![0; reader.output_buffer_size()];
+    let info = reader.next_frame(&mut buffer).map_err(error)?;
+    let channels = info.color_type.samples();
+
+    if info.width as usize != SCREEN_WIDTH || info.height as usize != SCREEN_HEIGHT {
+        return Err(format!("{} no mide {}x{}", path.display(), SCREEN_WIDTH, SCREEN_HEIGHT));
+    }
+
+    Ok(buffer[..info.buffer_size()]
+        .chunks(channels)
+        .map(|px| if channels >= 3 { shade(px[0], px[1], px[2]) } else { shade(px[0], px[0], px[0]) })
+        .collect())
+}
+
+fn main() {
+    let options = parse_args().unwrap_or_else(|e| {
+        eprintln!("{}\n{}", e, USAGE);
+        process::exit(2);
+    });
+
+    if let Err(e) = run(options) {
+        eprintln!("{}", e);
+        process::exit(1);
+    }
+}
@@ -0,0 +1,49 @@
+// Envolvente de volumen (NRx2)
+pub struct Envelope {
+    pub reg: u8,
+    pub volume: u8,
+    timer: u8,
+}
+
+impl Envelope {
+    pub fn new() -> Self {
+        Envelope {
+            reg: 0,
+            volume: 0,
+            timer: 0,
+        }
+    }
+
+    fn period(&self) -> u8 {
+        self.reg & 0b00000111
+    }
+
+    // El DAC esta encendido si alguno de los bits 3-7 esta activo
+    pub fn dac_enabled(&self) -> bool {
+        self.reg & 0b11111000 != 0
+    }
+
+    pub fn trigger(&mut self) {
+        self.volume = self.reg >> 4;
+        self.timer = if self.period() == 0 { 8 } else { self.period() };
+    }
+
+    pub fn clock(&mut self) {
+        if self.period() == 0 {
+            return;
+        }
+
+        self.timer = self.timer.saturating_sub(1);
+        if self.timer == 0 {
+            self.timer = self.period();
+
+            if self.reg & 0b00001000 != 0 {
+                if self.volume < 15 {
+                    self.volume += 1;
+                }
+            } else if self.volume > 0 {
+                self.volume -= 1;
+            }
+        }
+    }
+}
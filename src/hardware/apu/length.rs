@@ -0,0 +1,56 @@
+// Contador de duracion de los canales (NRx1 y bit 6 de NRx4)
+pub struct LengthCounter {
+    counter: u16,
+    max: u16,
+    pub enabled: bool,
+}
+
+impl LengthCounter {
+    pub fn new(max: u16) -> Self {
+        LengthCounter {
+            counter: 0,
+            max,
+            enabled: false,
+        }
+    }
+
+    // Al apagar el APU se desactiva pero en DMG conserva la cuenta
+    pub fn power_off(&mut self) {
+        self.enabled = false;
+    }
+
+    pub fn load(&mut self, val: u8) {
+        self.counter = self.max - val as u16;
+    }
+
+    // Devuelve true si el canal se tiene que apagar
+    pub fn clock(&mut self) -> bool {
+        if self.enabled && self.counter > 0 {
+            self.counter -= 1;
+            return self.counter == 0;
+        }
+        false
+    }
+
+    // Activar la duracion en la primera mitad de un periodo del secuenciador da un pulso extra.
+    // Devuelve true si el canal se tiene que apagar
+    pub fn write_enable(&mut self, enable: bool, next_step_clocks_length: bool) -> bool {
+        let was_enabled = self.enabled;
+        self.enabled = enable;
+
+        if !was_enabled && enable && !next_step_clocks_length {
+            return self.clock();
+        }
+        false
+    }
+
+    // Al disparar el canal con el contador a 0 se recarga al maximo, con el pulso extra si toca
+    pub fn trigger(&mut self, next_step_clocks_length: bool) {
+        if self.counter == 0 {
+            self.counter = self.max;
+            if self.enabled && !next_step_clocks_length {
+                self.counter -= 1;
+            }
+        }
+    }
+}
@@ -0,0 +1,423 @@
+use self::{noise::NoiseChannel, pulse::PulseChannel, wave::WaveChannel};
+
+mod envelope;
+mod length;
+mod noise;
+mod pulse;
+mod wave;
+
+// Frecuencia del reloj de la consola
+pub const CPU_FREQUENCY: u32 = 4194304;
+pub const DEFAULT_SAMPLE_RATE: u32 = 44100;
+// Frecuencia a la que se genera el audio antes de pasarlo por el Resampler
+pub const NATIVE_SAMPLE_RATE: u32 = CPU_FREQUENCY / 32;
+
+// Carga que conserva el condensador del filtro paso alto en cada ciclo (DMG)
+const HIGH_PASS_CHARGE: f64 = 0.999958;
+
+const NR10: usize = 0xFF10;
+const NR11: usize = 0xFF11;
+const NR14: usize = 0xFF14;
+const NR21: usize = 0xFF16;
+const NR24: usize = 0xFF19;
+const NR30: usize = 0xFF1A;
+const NR31: usize = 0xFF1B;
+const NR34: usize = 0xFF1E;
+const NR40: usize = 0xFF1F;
+const NR41: usize = 0xFF20;
+const NR44: usize = 0xFF23;
+const NR50: usize = 0xFF24;
+const NR51: usize = 0xFF25;
+const NR52: usize = 0xFF26;
+const WAVE_RAM_START: usize = 0xFF30;
+const WAVE_RAM_END: usize = 0xFF3F;
+
+// Filtro paso alto que quita la componente continua de la salida de los DAC
+struct HighPassFilter {
+    capacitor: f32,
+    charge: f32,
+}
+
+impl HighPassFilter {
+    fn new(sample_rate: u32) -> Self {
+        HighPassFilter {
+            capacitor: 0.0,
+            charge: HIGH_PASS_CHARGE.powf(CPU_FREQUENCY as f64 / sample_rate as f64) as f32,
+        }
+    }
+
+    fn apply(&mut self, input: f32) -> f32 {
+        let output = input - self.capacitor;
+        self.capacitor = input - output * self.charge;
+        output
+    }
+}
+
+// Salida de cada canal por separado, con el mismo panorama y volumen que en la mezcla
+struct Stems {
+    accumulated: [(f32, f32); 4],
+    high_pass: [(HighPassFilter, HighPassFilter); 4],
+    samples: [Vec<f32>; 4],
+}
+
+impl Stems {
+    fn new(sample_rate: u32) -> Self {
+        Stems {
+            accumulated: [(0.0, 0.0); 4],
+            high_pass: std::array::from_fn(|_| (HighPassFilter::new(sample_rate), HighPassFilter::new(sample_rate))),
+            samples: std::array::from_fn(|_| Vec::new()),
+        }
+    }
+}
+
+pub struct Apu {
+    ch1: PulseChannel,
+    ch2: PulseChannel,
+    ch3: WaveChannel,
+    ch4: NoiseChannel,
+
+    // Bits 0-2 volumen derecho, 4-6 izquierdo. Los bits 3 y 7 (VIN) mezclan el audio del
+    // cartucho, que ningun cartucho soportado genera
+    nr50: u8,
+    // Bits 0-3 canales a la derecha, 4-7 a la izquierda
+    nr51: u8,
+    power: bool,
+
+    // Siguiente paso del secuenciador (0 - 7)
+    frame_sequencer: u8,
+
+    sample_rate: u32,
+    sample_counter: u32,
+    // Suma de la salida durante los ciclos de la muestra actual, para promediarla
+    accumulated: (f32, f32),
+    accumulated_cycles: u32,
+    high_pass: (HighPassFilter, HighPassFilter),
+    // Muestras estereo intercaladas (izquierda, derecha)
+    samples: Vec<f32>,
+    stems: Option<Box<Stems>>,
+}
+
+impl Default for Apu {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Apu {
+    pub fn new() -> Self {
+        Apu {
+            ch1: PulseChannel::new(true),
+            ch2: PulseChannel::new(false),
+            ch3: WaveChannel::new(),
+            ch4: NoiseChannel::new(),
+
+            nr50: 0,
+            nr51: 0,
+            power: false,
+
+            frame_sequencer: 0,
+
+            sample_rate: DEFAULT_SAMPLE_RATE,
+            sample_counter: 0,
+            accumulated: (0.0, 0.0),
+            accumulated_cycles: 0,
+            high_pass: (HighPassFilter::new(DEFAULT_SAMPLE_RATE), HighPassFilter::new(DEFAULT_SAMPLE_RATE)),
+            samples: Vec::with_capacity(DEFAULT_SAMPLE_RATE as usize * 2),
+            stems: None,
+        }
+    }
+
+    pub fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
+    // Se descartan las muestras pendientes, estan generadas a la frecuencia anterior.
+    // La frecuencia se limita a 1 - CPU_FREQUENCY Hz
+    pub fn set_sample_rate(&mut self, sample_rate: u32) {
+        let sample_rate = sample_rate.clamp(1, CPU_FREQUENCY);
+
+        self.sample_rate = sample_rate;
+        self.sample_counter = 0;
+        self.accumulated = (0.0, 0.0);
+        self.accumulated_cycles = 0;
+        self.high_pass = (HighPassFilter::new(sample_rate), HighPassFilter::new(sample_rate));
+        self.samples = Vec::with_capacity(self.max_buffered_samples());
+        if self.stems.is_some() {
+            self.stems = Some(Box::new(Stems::new(sample_rate)));
+        }
+    }
+
+    // Guardar tambien la salida de cada canal por separado
+    pub fn set_stem_capture(&mut self, enabled: bool) {
+        self.stems = if enabled { Some(Box::new(Stems::new(self.sample_rate))) } else { None };
+    }
+
+    // Muestras de cada canal generadas desde la ultima llamada, None si no se estan guardando
+    pub fn take_stem_samples(&mut self) -> Option<[Vec<f32>; 4]> {
+        self.stems.as_mut().map(|stems| std::array::from_fn(|i| std::mem::take(&mut stems.samples[i])))
+    }
+
+    // 1 segundo de audio estereo
+    fn max_buffered_samples(&self) -> usize {
+        self.sample_rate as usize * 2
+    }
+
+    pub fn read(&self, dir: usize) -> u8 {
+        match dir {
+            NR10 ..= NR14 => self.ch1.read(dir - NR10),
+            NR21 ..= NR24 => self.ch2.read(dir - NR21 + 1),
+            NR30 ..= NR34 => self.ch3.read(dir - NR30),
+            NR40 ..= NR44 => self.ch4.read(dir - NR40),
+            NR50 => self.nr50,
+            NR51 => self.nr51,
+            NR52 => {
+                ((self.power as u8) << 7)
+                    | 0x70
+                    | ((self.ch4.enabled as u8) << 3)
+                    | ((self.ch3.enabled as u8) << 2)
+                    | ((self.ch2.enabled as u8) << 1)
+                    | self.ch1.enabled as u8
+            },
+            WAVE_RAM_START ..= WAVE_RAM_END => self.ch3.read_wave_ram(dir - WAVE_RAM_START),
+            _ => 0xFF,
+        }
+    }
+
+    pub fn write(&mut self, dir: usize, val: u8) {
+        // Apagado solo se puede escribir NR52, la wave RAM y en DMG la duracion de los canales
+        if !self.power {
+            match dir {
+                NR11 => self.ch1.write_length(val),
+                NR21 => self.ch2.write_length(val),
+                NR31 => self.ch3.write_length(val),
+                NR41 => self.ch4.write_length(val),
+                NR52 => self.write_power(val),
+                WAVE_RAM_START ..= WAVE_RAM_END => self.ch3.write_wave_ram(dir - WAVE_RAM_START, val),
+                _ => {},
+            }
+            return;
+        }
+
+        // Los pasos pares del secuenciador reducen la duracion
+        let next_step_clocks_length = self.frame_sequencer.is_multiple_of(2);
+
+        match dir {
+            NR10 ..= NR14 => self.ch1.write(dir - NR10, val, next_step_clocks_length),
+            NR21 ..= NR24 => self.ch2.write(dir - NR21 + 1, val, next_step_clocks_length),
+            NR30 ..= NR34 => self.ch3.write(dir - NR30, val, next_step_clocks_length),
+            NR40 ..= NR44 => self.ch4.write(dir - NR40, val, next_step_clocks_length),
+            NR50 => self.nr50 = val,
+            NR51 => self.nr51 = val,
+            NR52 => self.write_power(val),
+            WAVE_RAM_START ..= WAVE_RAM_END => self.ch3.write_wave_ram(dir - WAVE_RAM_START, val),
+            _ => {},
+        }
+    }
+
+    fn write_power(&mut self, val: u8) {
+        let power = val & 0b10000000 != 0;
+
+        if self.power && !power {
+            // Al apagar se borran NR10 - NR51
+            self.ch1.power_off();
+            self.ch2.power_off();
+            self.ch3.power_off();
+            self.ch4.power_off();
+            self.nr50 = 0;
+            self.nr51 = 0;
+        } else if !self.power && power {
+            // Al encender el siguiente paso del secuenciador es el 0
+            self.frame_sequencer = 0;
+        }
+
+        self.power = power;
+    }
+
+    // Flanco de bajada del bit 4 de DIV (bit 12 del divisor interno), 512 Hz
+    pub fn div_event(&mut self) {
+        if self.power {
+            self.frame_sequencer_step();
+        }
+    }
+
+    pub fn cycle(&mut self, cycles: u8) {
+        if self.power {
+            self.ch1.cycle(cycles as u16);
+            self.ch2.cycle(cycles as u16);
+            self.ch3.cycle(cycles as u16);
+            self.ch4.cycle(cycles as u16);
+        }
+
+        let channels = self.mix();
+        let left = channels.iter().map(|c| c.0).sum::<f32>();
+        let right = channels.iter().map(|c| c.1).sum::<f32>();
+
+        let mut to_cycle = cycles as u32;
+        while to_cycle > 0 {
+            // Ciclos que faltan para completar la siguiente muestra
+            let until_sample = (CPU_FREQUENCY - self.sample_counter).div_ceil(self.sample_rate);
+            let step = to_cycle.min(until_sample);
+
+            self.accumulated.0 += left * step as f32;
+            self.accumulated.1 += right * step as f32;
+            self.accumulated_cycles += step;
+            if let Some(stems) = self.stems.as_mut() {
+                for (accumulated, channel) in stems.accumulated.iter_mut().zip(channels) {
+                    accumulated.0 += channel.0 * step as f32;
+                    accumulated.1 += channel.1 * step as f32;
+                }
+            }
+            self.sample_counter += step * self.sample_rate;
+            to_cycle -= step;
+
+            if self.sample_counter >= CPU_FREQUENCY {
+                self.sample_counter -= CPU_FREQUENCY;
+                self.push_sample();
+            }
+        }
+    }
+
+    fn frame_sequencer_step(&mut self) {
+        match self.frame_sequencer {
+            0 | 4 => self.clock_lengths(),
+            2 | 6 => {
+                self.clock_lengths();
+                self.ch1.clock_sweep();
+            },
+            7 => {
+                self.ch1.clock_envelope();
+                self.ch2.clock_envelope();
+                self.ch4.clock_envelope();
+            },
+            _ => {},
+        }
+
+        self.frame_sequencer = (self.frame_sequencer + 1) % 8;
+    }
+
+    fn clock_lengths(&mut self) {
+        self.ch1.clock_length();
+        self.ch2.clock_length();
+        self.ch3.clock_length();
+        self.ch4.clock_length();
+    }
+
+    // Salida de los cuatro DAC (-1.0 - 1.0)
+    fn channel_outputs(&self) -> [f32; 4] {
+        [
+            dac_output(self.ch1.output(), self.ch1.dac_enabled()),
+            dac_output(self.ch2.output(), self.ch2.dac_enabled()),
+            dac_output(self.ch3.output(), self.ch3.dac_enabled()),
+            dac_output(self.ch4.output(), self.ch4.dac_enabled()),
+        ]
+    }
+
+    fn any_dac_enabled(&self) -> bool {
+        self.ch1.dac_enabled() || self.ch2.dac_enabled() || self.ch3.dac_enabled() || self.ch4.dac_enabled()
+    }
+
+    // Salida estereo de cada canal segun NR51 y NR50. La suma de los cuatro esta en -1.0 - 1.0
+    fn mix(&self) -> [(f32, f32); 4] {
+        if !self.power {
+            return [(0.0, 0.0); 4];
+        }
+
+        let left_volume = ((self.nr50 >> 4) & 0b111) as f32 + 1.0;
+        let right_volume = (self.nr50 & 0b111) as f32 + 1.0;
+        let outputs = self.channel_outputs();
+
+        std::array::from_fn(|i| {
+            let left = if self.nr51 & (0b00010000 << i) != 0 { outputs[i] } else { 0.0 };
+            let right = if self.nr51 & (0b00000001 << i) != 0 { outputs[i] } else { 0.0 };
+
+            (left * left_volume / 32.0, right * right_volume / 32.0)
+        })
+    }
+
+    fn push_sample(&mut self) {
+        let cycles = self.accumulated_cycles.max(1) as f32;
+        let left = self.accumulated.0 / cycles;
+        let right = self.accumulated.1 / cycles;
+        self.accumulated = (0.0, 0.0);
+        self.accumulated_cycles = 0;
+
+        // Sin ningun DAC encendido no hay nada que filtrar
+        let filter = self.any_dac_enabled();
+        let max = self.max_buffered_samples();
+
+        let (left, right) = filter_sample(&mut self.high_pass, (left, right), filter);
+        push_limited(&mut self.samples, max, left, right);
+
+        if let Some(stems) = self.stems.as_mut() {
+            for i in 0..4 {
+                let (left, right) = stems.accumulated[i];
+                stems.accumulated[i] = (0.0, 0.0);
+
+                let (left, right) = filter_sample(&mut stems.high_pass[i], (left / cycles, right / cycles), filter);
+                push_limited(&mut stems.samples[i], max, left, right);
+            }
+        }
+    }
+
+    // Devuelve las muestras generadas desde la ultima llamada
+    pub fn take_samples(&mut self) -> Vec<f32> {
+        std::mem::take(&mut self.samples)
+    }
+}
+
+fn filter_sample(high_pass: &mut (HighPassFilter, HighPassFilter), sample: (f32, f32), filter: bool) -> (f32, f32) {
+    if filter {
+        (high_pass.0.apply(sample.0), high_pass.1.apply(sample.1))
+    } else {
+        sample
+    }
+}
+
+// Si nadie recoge las muestras se descartan las mas antiguas
+fn push_limited(samples: &mut Vec<f32>, max: usize, left: f32, right: f32) {
+    if samples.len() >= max {
+        // Siempre un numero par para no intercambiar izquierda y derecha
+        samples.drain(..(max / 2) & !1);
+    }
+    samples.push(left);
+    samples.push(right);
+}
+
+// Convierte la salida digital 0 - 15 en analogica -1.0 - 1.0
+fn dac_output(digital: u8, dac_enabled: bool) -> f32 {
+    if dac_enabled {
+        digital as f32 / 7.5 - 1.0
+    } else {
+        0.0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn full_buffer_keeps_channels_with_odd_rate() {
+        let mut apu = Apu::new();
+        apu.set_sample_rate(11025);
+
+        // Canal 1 solo a la izquierda
+        apu.write(NR52, 0x80);
+        apu.write(NR50, 0x77);
+        apu.write(NR51, 0x10);
+        apu.write(NR11, 0x80);
+        apu.write(NR10 + 2, 0xF0);
+        apu.write(NR10 + 3, 0x00);
+        apu.write(NR14, 0x87);
+
+        // 1.5 s sin recoger las muestras
+        for _ in 0..CPU_FREQUENCY * 3 / 2 / 4 {
+            apu.cycle(4);
+        }
+
+        let samples = apu.take_samples();
+        assert_eq!(samples.len() % 2, 0);
+        assert!(samples.chunks(2).any(|s| s[0] != 0.0));
+        assert!(samples.chunks(2).all(|s| s[1] == 0.0));
+    }
+}
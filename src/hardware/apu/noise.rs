@@ -0,0 +1,136 @@
+use super::{envelope::Envelope, length::LengthCounter};
+
+const DIVISORS: [u16; 8] = [8, 16, 32, 48, 64, 80, 96, 112];
+
+pub struct NoiseChannel {
+    pub enabled: bool,
+    // NR43
+    polynomial: u8,
+    timer: u32,
+    lfsr: u16,
+    length: LengthCounter,
+    envelope: Envelope,
+}
+
+impl NoiseChannel {
+    pub fn new() -> Self {
+        NoiseChannel {
+            enabled: false,
+            polynomial: 0,
+            timer: 0,
+            lfsr: 0x7FFF,
+            length: LengthCounter::new(64),
+            envelope: Envelope::new(),
+        }
+    }
+
+    // reg: 0 = NR40 (no existe), 1 = NR41...
+    pub fn read(&self, reg: usize) -> u8 {
+        match reg {
+            0 | 1 => 0xFF,
+            2 => self.envelope.reg,
+            3 => self.polynomial,
+            4 => ((self.length.enabled as u8) << 6) | 0xBF,
+            _ => unreachable!(),
+        }
+    }
+
+    pub fn write(&mut self, reg: usize, val: u8, next_step_clocks_length: bool) {
+        match reg {
+            0 => {},
+            1 => self.length.load(val & 0b00111111),
+            2 => {
+                self.envelope.reg = val;
+                if !self.envelope.dac_enabled() {
+                    self.enabled = false;
+                }
+            },
+            3 => self.polynomial = val,
+            4 => {
+                if self.length.write_enable(val & 0b01000000 != 0, next_step_clocks_length) {
+                    self.enabled = false;
+                }
+
+                if val & 0b10000000 != 0 {
+                    self.trigger(next_step_clocks_length);
+                }
+            },
+            _ => unreachable!(),
+        }
+    }
+
+    pub fn write_length(&mut self, val: u8) {
+        self.length.load(val & 0b00111111);
+    }
+
+    pub fn power_off(&mut self) {
+        let mut length = std::mem::replace(&mut self.length, LengthCounter::new(64));
+        length.power_off();
+
+        *self = NoiseChannel::new();
+        self.length = length;
+    }
+
+    fn period(&self) -> u32 {
+        (DIVISORS[(self.polynomial & 0b111) as usize] as u32) << (self.polynomial >> 4)
+    }
+
+    fn trigger(&mut self, next_step_clocks_length: bool) {
+        self.enabled = self.envelope.dac_enabled();
+        self.length.trigger(next_step_clocks_length);
+        self.envelope.trigger();
+        self.lfsr = 0x7FFF;
+        self.timer = self.period();
+    }
+
+    pub fn cycle(&mut self, cycles: u16) {
+        let mut to_cycle = cycles as u32;
+
+        while to_cycle > 0 {
+            if self.timer > to_cycle {
+                self.timer -= to_cycle;
+                return;
+            }
+
+            to_cycle -= self.timer;
+            self.timer = self.period();
+
+            // Con shift 14 o 15 el LFSR no recibe pulsos
+            if self.polynomial >> 4 < 14 {
+                self.step_lfsr();
+            }
+        }
+    }
+
+    fn step_lfsr(&mut self) {
+        let xor = (self.lfsr & 0x01) ^ ((self.lfsr >> 1) & 0x01);
+        self.lfsr = (self.lfsr >> 1) | (xor << 14);
+
+        // Modo de 7 bits
+        if self.polynomial & 0b00001000 != 0 {
+            self.lfsr = (self.lfsr & !(1 << 6)) | (xor << 6);
+        }
+    }
+
+    pub fn clock_length(&mut self) {
+        if self.length.clock() {
+            self.enabled = false;
+        }
+    }
+
+    pub fn clock_envelope(&mut self) {
+        self.envelope.clock();
+    }
+
+    pub fn dac_enabled(&self) -> bool {
+        self.envelope.dac_enabled()
+    }
+
+    pub fn output(&self) -> u8 {
+        if !self.enabled || self.lfsr & 0x01 != 0 {
+            return 0;
+        }
+
+        self.envelope.volume
+    }
+}
@@ -0,0 +1,238 @@
+use super::{envelope::Envelope, length::LengthCounter};
+
+const DUTY_TABLE: [[u8; 8]; 4] = [
+    [0, 0, 0, 0, 0, 0, 0, 1], // 12.5%
+    [1, 0, 0, 0, 0, 0, 0, 1], // 25%
+    [1, 0, 0, 0, 0, 1, 1, 1], // 50%
+    [0, 1, 1, 1, 1, 1, 1, 0], // 75%
+];
+
+// Barrido de frecuencia del canal 1 (NR10)
+struct Sweep {
+    reg: u8,
+    enabled: bool,
+    shadow_frequency: u16,
+    timer: u8,
+    // Se ha calculado una frecuencia en modo resta desde el ultimo trigger
+    negate_used: bool,
+}
+
+impl Sweep {
+    fn period(&self) -> u8 {
+        (self.reg & 0b01110000) >> 4
+    }
+
+    fn negate(&self) -> bool {
+        self.reg & 0b00001000 != 0
+    }
+
+    fn shift(&self) -> u8 {
+        self.reg & 0b00000111
+    }
+
+    fn reload_timer(&mut self) {
+        self.timer = if self.period() == 0 { 8 } else { self.period() };
+    }
+
+    // Nueva frecuencia, None si se desborda de 11 bits
+    fn calculate(&mut self) -> Option<u16> {
+        let delta = self.shadow_frequency >> self.shift();
+
+        let frequency = if self.negate() {
+            self.negate_used = true;
+            self.shadow_frequency - delta
+        } else {
+            self.shadow_frequency + delta
+        };
+
+        if frequency > 2047 { None } else { Some(frequency) }
+    }
+}
+
+pub struct PulseChannel {
+    pub enabled: bool,
+    duty: u8,
+    duty_step: usize,
+    frequency: u16,
+    timer: u16,
+    length: LengthCounter,
+    envelope: Envelope,
+    sweep: Option<Sweep>,
+}
+
+impl PulseChannel {
+    pub fn new(with_sweep: bool) -> Self {
+        PulseChannel {
+            enabled: false,
+            duty: 0,
+            duty_step: 0,
+            frequency: 0,
+            timer: 0,
+            length: LengthCounter::new(64),
+            envelope: Envelope::new(),
+            sweep: if with_sweep {
+                Some(Sweep {
+                    reg: 0,
+                    enabled: false,
+                    shadow_frequency: 0,
+                    timer: 0,
+                    negate_used: false,
+                })
+            } else {
+                None
+            },
+        }
+    }
+
+    // reg: 0 = NRx0, 1 = NRx1... Los bits que no se pueden leer se devuelven a 1
+    pub fn read(&self, reg: usize) -> u8 {
+        match reg {
+            0 => self.sweep.as_ref().map_or(0xFF, |sweep| sweep.reg | 0x80),
+            1 => (self.duty << 6) | 0x3F,
+            2 => self.envelope.reg,
+            3 => 0xFF,
+            4 => ((self.length.enabled as u8) << 6) | 0xBF,
+            _ => unreachable!(),
+        }
+    }
+
+    pub fn write(&mut self, reg: usize, val: u8, next_step_clocks_length: bool) {
+        match reg {
+            0 => {
+                if let Some(sweep) = self.sweep.as_mut() {
+                    let was_negate = sweep.negate();
+                    sweep.reg = val;
+
+                    // Quitar el modo resta despues de haberlo usado apaga el canal
+                    if was_negate && !sweep.negate() && sweep.negate_used {
+                        self.enabled = false;
+                    }
+                }
+            },
+            1 => {
+                self.duty = val >> 6;
+                self.length.load(val & 0b00111111);
+            },
+            2 => {
+                self.envelope.reg = val;
+                if !self.envelope.dac_enabled() {
+                    self.enabled = false;
+                }
+            },
+            3 => self.frequency = (self.frequency & 0x0700) | val as u16,
+            4 => {
+                self.frequency = (self.frequency & 0x00FF) | ((val as u16 & 0b111) << 8);
+
+                if self.length.write_enable(val & 0b01000000 != 0, next_step_clocks_length) {
+                    self.enabled = false;
+                }
+
+                if val & 0b10000000 != 0 {
+                    self.trigger(next_step_clocks_length);
+                }
+            },
+            _ => unreachable!(),
+        }
+    }
+
+    // Con el APU apagado solo se puede escribir la duracion de NRx1
+    pub fn write_length(&mut self, val: u8) {
+        self.length.load(val & 0b00111111);
+    }
+
+    // Borra todos los registros menos el contador de duracion
+    pub fn power_off(&mut self) {
+        let mut length = std::mem::replace(&mut self.length, LengthCounter::new(64));
+        length.power_off();
+
+        *self = PulseChannel::new(self.sweep.is_some());
+        self.length = length;
+    }
+
+    fn trigger(&mut self, next_step_clocks_length: bool) {
+        self.enabled = self.envelope.dac_enabled();
+        self.length.trigger(next_step_clocks_length);
+        self.timer = (2048 - self.frequency) * 4;
+        self.envelope.trigger();
+
+        if let Some(sweep) = self.sweep.as_mut() {
+            sweep.shadow_frequency = self.frequency;
+            sweep.negate_used = false;
+            sweep.reload_timer();
+            sweep.enabled = sweep.period() != 0 || sweep.shift() != 0;
+
+            // Con shift se comprueba el desbordamiento inmediatamente
+            if sweep.shift() != 0 && sweep.calculate().is_none() {
+                self.enabled = false;
+            }
+        }
+    }
+
+    pub fn cycle(&mut self, cycles: u16) {
+        let mut to_cycle = cycles;
+
+        while to_cycle > 0 {
+            if self.timer > to_cycle {
+                self.timer -= to_cycle;
+                return;
+            }
+
+            to_cycle -= self.timer;
+            self.timer = (2048 - self.frequency) * 4;
+            self.duty_step = (self.duty_step + 1) % 8;
+        }
+    }
+
+    pub fn clock_length(&mut self) {
+        if self.length.clock() {
+            self.enabled = false;
+        }
+    }
+
+    pub fn clock_envelope(&mut self) {
+        self.envelope.clock();
+    }
+
+    pub fn clock_sweep(&mut self) {
+        let Some(sweep) = self.sweep.as_mut() else {
+            return;
+        };
+
+        sweep.timer = sweep.timer.saturating_sub(1);
+        if sweep.timer > 0 {
+            return;
+        }
+        sweep.reload_timer();
+
+        if !sweep.enabled || sweep.period() == 0 {
+            return;
+        }
+
+        match sweep.calculate() {
+            Some(frequency) if sweep.shift() != 0 => {
+                sweep.shadow_frequency = frequency;
+                self.frequency = frequency;
+
+                // Se vuelve a calcular solo para comprobar el desbordamiento
+                if sweep.calculate().is_none() {
+                    self.enabled = false;
+                }
+            },
+            Some(_) => {},
+            None => self.enabled = false,
+        }
+    }
+
+    pub fn dac_enabled(&self) -> bool {
+        self.envelope.dac_enabled()
+    }
+
+    // Salida digital 0 - 15
+    pub fn output(&self) -> u8 {
+        if !self.enabled {
+            return 0;
+        }
+
+        DUTY_TABLE[self.duty as usize][self.duty_step] * self.envelope.volume
+    }
+}
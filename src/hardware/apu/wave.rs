@@ -0,0 +1,179 @@
+use super::length::LengthCounter;
+
+pub struct WaveChannel {
+    pub enabled: bool,
+    dac_enabled: bool,
+    // Nivel de salida (NR32 bits 5-6)
+    output_level: u8,
+    frequency: u16,
+    timer: u16,
+    position: usize,
+    sample_buffer: u8,
+    // Ciclos desde la ultima lectura de la wave RAM, para los accesos de la CPU mientras suena
+    cycles_since_fetch: u16,
+    length: LengthCounter,
+    wave_ram: [u8; 0x10],
+}
+
+impl WaveChannel {
+    pub fn new() -> Self {
+        WaveChannel {
+            enabled: false,
+            dac_enabled: false,
+            output_level: 0,
+            frequency: 0,
+            timer: 0,
+            position: 0,
+            sample_buffer: 0,
+            cycles_since_fetch: u16::MAX,
+            length: LengthCounter::new(256),
+            wave_ram: [0x00; 0x10],
+        }
+    }
+
+    // reg: 0 = NR30, 1 = NR31...
+    pub fn read(&self, reg: usize) -> u8 {
+        match reg {
+            0 => ((self.dac_enabled as u8) << 7) | 0x7F,
+            1 => 0xFF,
+            2 => (self.output_level << 5) | 0x9F,
+            3 => 0xFF,
+            4 => ((self.length.enabled as u8) << 6) | 0xBF,
+            _ => unreachable!(),
+        }
+    }
+
+    pub fn write(&mut self, reg: usize, val: u8, next_step_clocks_length: bool) {
+        match reg {
+            0 => {
+                self.dac_enabled = val & 0b10000000 != 0;
+                if !self.dac_enabled {
+                    self.enabled = false;
+                }
+            },
+            1 => self.length.load(val),
+            2 => self.output_level = (val & 0b01100000) >> 5,
+            3 => self.frequency = (self.frequency & 0x0700) | val as u16,
+            4 => {
+                self.frequency = (self.frequency & 0x00FF) | ((val as u16 & 0b111) << 8);
+
+                if self.length.write_enable(val & 0b01000000 != 0, next_step_clocks_length) {
+                    self.enabled = false;
+                }
+
+                if val & 0b10000000 != 0 {
+                    self.trigger(next_step_clocks_length);
+                }
+            },
+            _ => unreachable!(),
+        }
+    }
+
+    // En DMG, mientras el canal suena solo se accede a la wave RAM si coincide con una lectura
+    // del propio canal, y entonces se accede al byte que esta leyendo
+    pub fn read_wave_ram(&self, index: usize) -> u8 {
+        if !self.enabled {
+            return self.wave_ram[index];
+        }
+
+        if self.cycles_since_fetch < 2 {
+            self.wave_ram[self.position / 2]
+        } else {
+            0xFF
+        }
+    }
+
+    pub fn write_wave_ram(&mut self, index: usize, val: u8) {
+        if !self.enabled {
+            self.wave_ram[index] = val;
+        } else if self.cycles_since_fetch < 2 {
+            self.wave_ram[self.position / 2] = val;
+        }
+    }
+
+    pub fn write_length(&mut self, val: u8) {
+        self.length.load(val);
+    }
+
+    // La wave RAM no se borra al apagar el APU
+    pub fn power_off(&mut self) {
+        let mut length = std::mem::replace(&mut self.length, LengthCounter::new(256));
+        length.power_off();
+        let wave_ram = self.wave_ram;
+
+        *self = WaveChannel::new();
+        self.length = length;
+        self.wave_ram = wave_ram;
+    }
+
+    fn trigger(&mut self, next_step_clocks_length: bool) {
+        // En DMG, volver a disparar el canal justo cuando va a leer corrompe el principio de la wave RAM
+        if self.enabled && self.timer == 2 {
+            let next = ((self.position + 1) % 32) / 2;
+            if next < 4 {
+                self.wave_ram[0] = self.wave_ram[next];
+            } else {
+                let block = next & !0b11;
+                self.wave_ram.copy_within(block..block + 4, 0);
+            }
+        }
+
+        self.enabled = self.dac_enabled;
+        self.length.trigger(next_step_clocks_length);
+        self.position = 0;
+        // Retraso de 6 ciclos antes de la primera lectura
+        self.timer = (2048 - self.frequency) * 2 + 6;
+    }
+
+    pub fn cycle(&mut self, cycles: u16) {
+        self.cycles_since_fetch = self.cycles_since_fetch.saturating_add(cycles);
+
+        if !self.enabled {
+            return;
+        }
+
+        let mut to_cycle = cycles;
+
+        while to_cycle > 0 {
+            if self.timer > to_cycle {
+                self.timer -= to_cycle;
+                return;
+            }
+
+            to_cycle -= self.timer;
+            self.timer = (2048 - self.frequency) * 2;
+            self.position = (self.position + 1) % 32;
+            self.sample_buffer = self.wave_ram[self.position / 2];
+            self.cycles_since_fetch = to_cycle;
+        }
+    }
+
+    pub fn clock_length(&mut self) {
+        if self.length.clock() {
+            self.enabled = false;
+        }
+    }
+
+    pub fn dac_enabled(&self) -> bool {
+        self.dac_enabled
+    }
+
+    pub fn output(&self) -> u8 {
+        if !self.enabled {
+            return 0;
+        }
+
+        let sample = if self.position.is_multiple_of(2) {
+            self.sample_buffer >> 4
+        } else {
+            self.sample_buffer & 0x0F
+        };
+
+        match self.output_level {
+            0 => 0,
+            1 => sample,
+            2 => sample >> 1,
+            _ => sample >> 2,
+        }
+    }
+}
@@ -0,0 +1,171 @@
+use std::{f64::consts::PI, fmt};
+
+use super::apu::CPU_FREQUENCY;
+
+// Cruces por cero del sinc a cada lado del centro
+const ZERO_CROSSINGS: f64 = 12.0;
+// Puntos de la tabla del nucleo por muestra de entrada
+const KERNEL_RESOLUTION: usize = 64;
+// Frecuencias minima y maxima del resampler. Por debajo el nucleo se haria enorme
+pub const MIN_SAMPLE_RATE: u32 = 1000;
+pub const MAX_SAMPLE_RATE: u32 = 384000;
+// Limites de AudioSink::rate_adjustment
+const MIN_RATE_ADJUSTMENT: f64 = 0.5;
+const MAX_RATE_ADJUSTMENT: f64 = 2.0;
+
+// Destino del audio, recibe las muestras de cada frame ya convertidas a su frecuencia
+pub trait AudioSink {
+    fn sample_rate(&self) -> u32;
+
+    // Muestras estereo intercaladas (izquierda, derecha) en -1.0 - 1.0
+    fn push_samples(&mut self, samples: &[f32]);
+
+    // Factor que se aplica a la frecuencia de salida para compensar las diferencias de reloj
+    // con el dispositivo de audio. Menor que 1.0 genera menos muestras
+    fn rate_adjustment(&self) -> f64 {
+        1.0
+    }
+}
+
+// Frecuencia de un AudioSink fuera de MIN_SAMPLE_RATE - MAX_SAMPLE_RATE
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct UnsupportedSampleRate(pub u32);
+
+impl fmt::Display for UnsupportedSampleRate {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Frecuencia de muestreo no soportada: {} Hz (entre {} y {})", self.0, MIN_SAMPLE_RATE, MAX_SAMPLE_RATE)
+    }
+}
+
+impl std::error::Error for UnsupportedSampleRate {}
+
+// Cambio de frecuencia de muestreo estereo con un sinc enventanado (Blackman)
+pub struct Resampler {
+    input_rate: u32,
+    output_rate: u32,
+    // Mitad del ancho del nucleo en muestras de entrada
+    half_width: f64,
+    // Mitad derecha del nucleo, simetrico
+    kernel: Vec<f32>,
+    // Muestras de entrada pendientes, estereo intercaladas
+    input: Vec<f32>,
+    // Posicion de la siguiente muestra de salida, en muestras de entrada
+    position: f64,
+}
+
+impl Resampler {
+    // Las frecuencias se limitan a MIN_SAMPLE_RATE - MAX_SAMPLE_RATE (la de entrada hasta CPU_FREQUENCY)
+    pub fn new(input_rate: u32, output_rate: u32) -> Self {
+        let input_rate = input_rate.clamp(MIN_SAMPLE_RATE, CPU_FREQUENCY);
+        let output_rate = output_rate.clamp(MIN_SAMPLE_RATE, MAX_SAMPLE_RATE);
+
+        // Corte un poco por debajo de la frecuencia de Nyquist menor, relativo a la de entrada
+        let cutoff = (output_rate as f64 / input_rate as f64).min(1.0) * 0.95;
+        let half_width = ZERO_CROSSINGS / cutoff;
+
+        let len = (half_width * KERNEL_RESOLUTION as f64).ceil() as usize + 2;
+        let kernel = (0..len)
+            .map(|i| {
+                let t = i as f64 / KERNEL_RESOLUTION as f64;
+                if t >= half_width {
+                    return 0.0;
+                }
+
+                let x = PI * t * cutoff;
+                let sinc = if x == 0.0 { 1.0 } else { x.sin() / x };
+                let window = 0.42 + 0.5 * (PI * t / half_width).cos() + 0.08 * (2.0 * PI * t / half_width).cos();
+
+                (sinc * window) as f32
+            })
+            .collect();
+
+        // Se empieza con silencio para tener muestras a la izquierda de la primera salida
+        let padding = half_width.ceil() as usize;
+
+        Resampler {
+            input_rate,
+            output_rate,
+            half_width,
+            kernel,
+            input: vec![0.0; padding * 2],
+            position: padding as f64,
+        }
+    }
+
+    pub fn input_rate(&self) -> u32 {
+        self.input_rate
+    }
+
+    pub fn output_rate(&self) -> u32 {
+        self.output_rate
+    }
+
+    fn kernel_at(&self, t: f64) -> f32 {
+        let index = t.abs() * KERNEL_RESOLUTION as f64;
+        let i = index as usize;
+        if i + 1 >= self.kernel.len() {
+            return 0.0;
+        }
+
+        let frac = (index - i as f64) as f32;
+        self.kernel[i] + (self.kernel[i + 1] - self.kernel[i]) * frac
+    }
+
+    // Añade las muestras de entrada y escribe en output todas las de salida que ya se pueden calcular.
+    // El ajuste de frecuencia se limita a 0.5 - 2.0, y si no es un numero finito se usa 1.0
+    pub fn process(&mut self, samples: &[f32], rate_adjustment: f64, output: &mut Vec<f32>) {
+        self.input.extend_from_slice(samples);
+
+        let rate_adjustment = if rate_adjustment.is_finite() {
+            rate_adjustment.clamp(MIN_RATE_ADJUSTMENT, MAX_RATE_ADJUSTMENT)
+        } else {
+            1.0
+        };
+
+        let frames = (self.input.len() / 2) as f64;
+        let step = self.input_rate as f64 / (self.output_rate as f64 * rate_adjustment);
+
+        while self.position + self.half_width < frames {
+            let first = (self.position - self.half_width).ceil().max(0.0) as usize;
+            let last = (self.position + self.half_width).floor() as usize;
+
+            let mut left = 0.0;
+            let mut right = 0.0;
+            let mut total = 0.0;
+
+            for i in first..=last {
+                let weight = self.kernel_at(i as f64 - self.position);
+                left += self.input[i * 2] * weight;
+                right += self.input[i * 2 + 1] * weight;
+                total += weight;
+            }
+
+            // Normalizar por la suma de los pesos mantiene la ganancia en continua a 1
+            output.push(left / total);
+            output.push(right / total);
+            self.position += step;
+        }
+
+        // Se descartan las muestras que ya no alcanza el nucleo
+        let consumed = (self.position - self.half_width).floor().max(0.0) as usize;
+        self.input.drain(..consumed * 2);
+        self.position -= consumed as f64;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn invalid_rate_adjustment() {
+        for adjustment in [0.0, -1.0, f64::NAN, f64::INFINITY] {
+            let mut resampler = Resampler::new(CPU_FREQUENCY / 32, 44100);
+            let mut output = Vec::new();
+            resampler.process(&[0.0; 4000], adjustment, &mut output);
+
+            assert!(!output.is_empty());
+            assert_eq!(output.len() % 2, 0);
+        }
+    }
+}
@@ -1,25 +1,43 @@
-use super::{mbc::MbcController, ppu::PPU};
+use super::{apu::Apu, joypad::{Joypad, Keys}, mbc::{MbcController, TimeSource}, ppu::PPU, serial::{Serial, SerialDevice}, timer::Timer};
 
 // Interrupciones
 pub enum Interrupts {
     VBlank,
     LcdStat,
     Timer,
-    // Serial,
+    Serial,
     Joypad,
 }
 
+// Transferencia OAM DMA en curso
+struct Dma {
+    source: usize,
+    index: usize,
+    cycles: u8,
+}
+
 pub struct Bus {
     rom: Option<Box<dyn MbcController>>,    // 0x0000 - 0x7FFF
     pub ppu: PPU,                               // 0x8000 - 0x9FFF
-    eram: [u8; 0x2000],                         // 0xA000 - 0xBFFF
+    pub apu: Apu,                               // 0xFF10 - 0xFF3F
+    joypad: Joypad,                             // 0xFF00
+    serial: Serial,                             // 0xFF01 - 0xFF02
+    timer: Timer,                               // 0xFF04 - 0xFF07
+                                                // 0xA000 - 0xBFFF (RAM del cartucho, en el MBC)
     wram: [u8; 0x2000],                         // 0xC000 - 0xDFFF (0xE000 - 0xFDFF)
     hram: [u8; 0x200],                          // 0xFE00 - 0xFFFF
     boot_rom: [u8; 0x100],
     enable_boot_rom: bool,
 
-    internat_div_counter: u16,
-    last_and_result: bool,
+    dma: Option<Dma>,
+    // Se ha escrito en la RAM del cartucho desde el ultimo guardado
+    ram_dirty: bool,
+}
+
+impl Default for Bus {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl Bus {
@@ -27,7 +45,10 @@ impl Bus {
         Bus {
             rom: None,
             ppu: PPU::new(),
-            eram: [0x00; 0x2000],
+            apu: Apu::new(),
+            joypad: Joypad::new(),
+            serial: Serial::new(),
+            timer: Timer::new(),
             wram: [0x00; 0x2000],
             hram: [0x00; 0x200],
             boot_rom: [
@@ -50,8 +71,8 @@ impl Bus {
             ],
             enable_boot_rom: true,
 
-            internat_div_counter: 0,
-            last_and_result: false,
+            dma: None,
+            ram_dirty: false,
         }
     }
 
@@ -59,20 +80,58 @@ impl Bus {
         self.rom = rom;
     }
 
+    pub fn set_time_source(&mut self, time_source: Box<dyn TimeSource>) {
+        if let Some(rom) = self.rom.as_mut() {
+            rom.set_time_source(time_source);
+        }
+    }
+
+    // Datos a guardar en el .sav si el cartucho tiene pila
+    pub fn battery_save_data(&self) -> Option<Vec<u8>> {
+        self.rom.as_ref().filter(|rom| rom.has_battery()).map(|rom| rom.save_data())
+    }
+
+    pub fn load_battery_save_data(&mut self, data: &[u8]) {
+        if let Some(rom) = self.rom.as_mut() {
+            rom.load_save_data(data);
+        }
+    }
+
+    pub fn ram_dirty(&self) -> bool {
+        self.ram_dirty
+    }
+
+    pub fn clear_ram_dirty(&mut self) {
+        self.ram_dirty = false;
+    }
+
+    pub fn rumble(&self) -> bool {
+        self.rom.as_ref().is_some_and(|rom| rom.rumble())
+    }
+
     pub fn read(&self, dir: usize) -> u8 {
+        // Durante la DMA la CPU solo puede acceder a HRAM y a los registros
+        if self.dma.is_some() && dir < 0xFF00 {
+            return 0xFF;
+        }
+
+        self.read_raw(dir)
+    }
+
+    fn read_raw(&self, dir: usize) -> u8 {
         match dir {
             0x0000 ..= 0x7FFF => {
                 if self.read(0xFF50) == 0 && dir < 0x100 && self.enable_boot_rom {
                     self.boot_rom[dir]
                 } else {
-                    self.rom.as_ref().unwrap().read(dir)
+                    self.rom.as_ref().map_or(0xFF, |rom| rom.read(dir))
                 }
             },
             0x8000 ..= 0x9FFF => self.ppu.read_vram(dir),
-            0xA000 ..= 0xBFFF => self.eram[dir - 0xA000],
+            0xA000 ..= 0xBFFF => self.rom.as_ref().map_or(0xFF, |rom| rom.read_ram(dir)),
             0xC000 ..= 0xDFFF => self.wram[dir - 0xC000],
             0xE000 ..= 0xFDFF => {
-                if dir >= 0xFEA0 && dir <= 0xFEFF {
+                if (0xFEA0..=0xFEFF).contains(&dir) {
                     return 0x00;
                 };
                 self.wram[dir - 0xE000]
@@ -81,7 +140,11 @@ impl Bus {
             0xFEA0 ..= 0xFEFF => 0x00,
             0xFF00 ..= 0xFF7F => {
                 match dir {
-                    0xFF40 ..= 0xFF4B => self.ppu.regs[dir - 0xFF40],
+                    0xFF00 => self.joypad.read(),
+                    0xFF01 ..= 0xFF02 => self.serial.read(dir),
+                    0xFF04 ..= 0xFF07 => self.timer.read(dir),
+                    0xFF10 ..= 0xFF3F => self.apu.read(dir),
+                    0xFF40 ..= 0xFF4B => self.ppu.read_reg(dir),
                     _ => self.hram[dir - 0xFE00],
                 }
             }
@@ -91,10 +154,25 @@ impl Bus {
     }
 
     pub fn write(&mut self, dir: usize, val: u8) {
+        if self.dma.is_some() && dir < 0xFF00 {
+            return;
+        }
+
         match dir {
-            0x0000 ..= 0x7FFF => self.rom.as_mut().unwrap().write(dir, val),
+            0x0000 ..= 0x7FFF => {
+                if let Some(rom) = self.rom.as_mut() {
+                    rom.write(dir, val);
+                }
+            },
             0x8000 ..= 0x9FFF => self.ppu.write_vram(dir, val),
-            0xA000 ..= 0xBFFF => self.eram[dir - 0xA000] = val,
+            0xA000 ..= 0xBFFF => {
+                if let Some(rom) = self.rom.as_mut() {
+                    // Solo hace falta guardar si el byte ha llegado a la RAM con pila
+                    if rom.write_ram(dir, val) && rom.has_battery() {
+                        self.ram_dirty = true;
+                    }
+                }
+            },
             0xC000 ..= 0xDFFF => self.wram[dir - 0xC000] = val,
             0xE000 ..= 0xFDFF => self.wram[dir - 0xE000] = val,
             0xFE00 ..= 0xFE9F => self.ppu.write_oam(dir, val),
@@ -102,11 +180,26 @@ impl Bus {
             0xFF00 ..= 0xFF7F => {
                 match dir {
                     0xFF00 => {
-                        let joyp = self.hram[0x100] & 0x0F;
-                        self.hram[0x100] = joyp | (val & 0xF0);
+                        if self.joypad.write(val) {
+                            self.set_int(Interrupts::Joypad);
+                        }
+                    },
+                    0xFF01 ..= 0xFF02 => self.serial.write(dir, val),
+                    0xFF04 ..= 0xFF07 => {
+                        if self.timer.write(dir, val) {
+                            self.apu.div_event();
+                        }
+                    },
+                    0xFF10 ..= 0xFF3F => self.apu.write(dir, val),
+                    0xFF46 => {
+                        self.ppu.regs[dir - 0xFF40] = val;
+                        self.dma = Some(Dma {
+                            source: (val as usize) << 8,
+                            index: 0,
+                            cycles: 0,
+                        });
                     }
-                    0xFF04 => self.hram[dir - 0xFE00] = 0,
-                    0xFF40 ..= 0xFF4B => self.ppu.regs[dir - 0xFF40] = val,
+                    0xFF40 ..= 0xFF4B => self.ppu.write_reg(dir, val),
                     _ => self.hram[dir - 0xFE00] = val,
                 }
             }
@@ -115,8 +208,19 @@ impl Bus {
         }
     }
 
-    pub fn set_joyp(&mut self, val: u8) {
-        self.hram[0x100] = val;
+    // Devuelve el dispositivo que habia conectado
+    pub fn set_serial_device(&mut self, device: Option<Box<dyn SerialDevice>>) -> Option<Box<dyn SerialDevice>> {
+        self.serial.set_device(device)
+    }
+
+    pub fn take_serial_error(&mut self) -> Option<std::io::Error> {
+        self.serial.take_device_error()
+    }
+
+    pub fn set_key(&mut self, key: Keys, pressed: bool) {
+        if self.joypad.set_key(key, pressed) {
+            self.set_int(Interrupts::Joypad);
+        }
     }
 
     pub fn set_enable_boot_rom(mut self, enable_boot_rom: bool) -> Bus {
@@ -125,8 +229,12 @@ impl Bus {
     }
 
     pub fn reset(&mut self) {
+        // Valor del contador interno de DIV al terminar el boot ROM de DMG
+        self.timer.set_counter(0xABCC);
         self.write(0xFF06, 0x00);
         self.write(0xFF07, 0x00);
+        // Con el APU apagado se ignoran las escrituras a sus registros
+        self.write(0xFF26, 0xF1);
         self.write(0xFF10, 0x80);
         self.write(0xFF05, 0x00);
         self.write(0xFF11, 0xBF);
@@ -145,7 +253,6 @@ impl Bus {
         self.write(0xFF23, 0xBF);
         self.write(0xFF24, 0x77);
         self.write(0xFF25, 0xF3);
-        self.write(0xFF26, 0xF1);
         self.write(0xFF40, 0x91);
         self.write(0xFF42, 0x00);
         self.write(0xFF43, 0x00);
@@ -159,18 +266,7 @@ impl Bus {
     }
 
     pub fn reset_joyp(&mut self) {
-        self.hram[0x100] = 0xFF;
-    }
-
-    pub fn increase_div(&mut self) {
-        let val = self.read(0xFF04).wrapping_add(1);
-        self.write(0xFF04, val);
-    }
-
-    pub fn increase_tima(&mut self) -> bool {
-        let val = self.read(0xFF05).overflowing_add(1);
-        self.write(0xFF05, val.0);
-        val.1
+        self.joypad = Joypad::new();
     }
 
     pub fn cycle(&mut self, cycles: u8) {
@@ -186,53 +282,59 @@ impl Bus {
             (false, true) => self.set_int(Interrupts::LcdStat),
         }
 
-        self.update_tima(cycles);
-    }
+        self.apu.cycle(cycles);
+        self.dma_cycle(cycles);
 
-    pub fn set_int(&mut self, int: Interrupts) {
-        let mut int_f = self.read(0xFF0F);
-        match int {
-            Interrupts::VBlank => int_f |= 0b00000001,
-            Interrupts::LcdStat => int_f |= 0b00000010,
-            Interrupts::Timer => int_f |= 0b00000100,
-            //Interrupts::Serial => int_f |= 0b00001000,
-            Interrupts::Joypad => int_f |= 0b00010000,
+        let (timer_int, div_apu_events) = self.timer.cycle(cycles);
+        if timer_int {
+            self.set_int(Interrupts::Timer);
+        }
+        for _ in 0..div_apu_events {
+            self.apu.div_event();
         }
 
-        self.write(0xFF0F, int_f);
+        if self.serial.cycle(cycles) {
+            self.set_int(Interrupts::Serial);
+        }
     }
 
-    fn update_tima(&mut self, cycles: u8) {
-        let mut to_cycle = cycles;
-        let bit_pos = match self.read(0xFF07) & 0b00000011 {
-            0b00 => 9,
-            0b01 => 3,
-            0b10 => 5,
-            0b11 => 7,
-            _ => unreachable!()
+    // Copia un byte a la OAM por cada M-ciclo, 160 en total
+    fn dma_cycle(&mut self, cycles: u8) {
+        let Some(mut dma) = self.dma.take() else {
+            return;
         };
-        let tac = self.read(0xFF07);
 
-        while to_cycle > 0 {
-            let bit_div = self.internat_div_counter & (1 << bit_pos) != 0;
-            let timer_enable_bit = tac & 0b100 != 0;
+        dma.cycles += cycles;
 
-            let and_result = bit_div & timer_enable_bit;
-
-            if !and_result && self.last_and_result {
-                let int = self.increase_tima();
-
-                if int {
-                    let tma = self.read(0xFF06);
-                    self.write(0xFF05, tma);
-                    self.set_int(Interrupts::Timer);
-                }
+        while dma.cycles >= 4 && dma.index < 0xA0 {
+            let src = dma.source + dma.index;
+            // Las paginas 0xE0 - 0xFF leen de la copia de la WRAM
+            let val = if src >= 0xE000 {
+                self.wram[(src - 0xE000) & 0x1FFF]
+            } else {
+                self.read_raw(src)
             };
+            self.ppu.write_oam(0xFE00 + dma.index, val);
 
-            self.internat_div_counter = self.internat_div_counter.wrapping_add(1);
-            self.last_and_result = and_result;
+            dma.index += 1;
+            dma.cycles -= 4;
+        }
 
-            to_cycle -= 1;
+        if dma.index < 0xA0 {
+            self.dma = Some(dma);
         }
     }
-}
\ No newline at end of file
+
+    pub fn set_int(&mut self, int: Interrupts) {
+        let mut int_f = self.read(0xFF0F);
+        match int {
+            Interrupts::VBlank => int_f |= 0b00000001,
+            Interrupts::LcdStat => int_f |= 0b00000010,
+            Interrupts::Timer => int_f |= 0b00000100,
+            Interrupts::Serial => int_f |= 0b00001000,
+            Interrupts::Joypad => int_f |= 0b00010000,
+        }
+
+        self.write(0xFF0F, int_f);
+    }
+}
@@ -0,0 +1,370 @@
+use std::fmt;
+
+// Logo de Nintendo (0x0104 - 0x0133), el boot ROM se bloquea si no coincide
+const NINTENDO_LOGO: [u8; 0x30] = [
+    0xCE, 0xED, 0x66, 0x66, 0xCC, 0x0D, 0x00, 0x0B, 0x03, 0x73, 0x00, 0x83, 0x00, 0x0C, 0x00, 0x0D,
+    0x00, 0x08, 0x11, 0x1F, 0x88, 0x89, 0x00, 0x0E, 0xDC, 0xCC, 0x6E, 0xE6, 0xDD, 0xDD, 0xD9, 0x99,
+    0xBB, 0xBB, 0x67, 0x63, 0x6E, 0x0E, 0xEC, 0xCC, 0xDD, 0xDC, 0x99, 0x9F, 0xBB, 0xB9, 0x33, 0x3E,
+];
+
+// La cabecera ocupa 0x0100 - 0x014F
+pub const HEADER_END: usize = 0x0150;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Mbc {
+    None,
+    Mbc1,
+    Mbc2,
+    Mbc3,
+    Mbc5,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CartridgeType {
+    pub code: u8,
+    pub mbc: Mbc,
+    pub ram: bool,
+    pub battery: bool,
+    pub timer: bool,
+    pub rumble: bool,
+}
+
+impl CartridgeType {
+    // None si el tipo no existe o no esta implementado (MMM01, MBC6, MBC7, HuC...)
+    pub fn from_code(code: u8) -> Option<Self> {
+        // (MBC, RAM, pila, reloj, vibracion)
+        let (mbc, ram, battery, timer, rumble) = match code {
+            0x00 => (Mbc::None, false, false, false, false),
+            0x01 => (Mbc::Mbc1, false, false, false, false),
+            0x02 => (Mbc::Mbc1, true, false, false, false),
+            0x03 => (Mbc::Mbc1, true, true, false, false),
+            0x05 => (Mbc::Mbc2, false, false, false, false),
+            0x06 => (Mbc::Mbc2, false, true, false, false),
+            0x08 => (Mbc::None, true, false, false, false),
+            0x09 => (Mbc::None, true, true, false, false),
+            0x0F => (Mbc::Mbc3, false, true, true, false),
+            0x10 => (Mbc::Mbc3, true, true, true, false),
+            0x11 => (Mbc::Mbc3, false, false, false, false),
+            0x12 => (Mbc::Mbc3, true, false, false, false),
+            0x13 => (Mbc::Mbc3, true, true, false, false),
+            0x19 => (Mbc::Mbc5, false, false, false, false),
+            0x1A => (Mbc::Mbc5, true, false, false, false),
+            0x1B => (Mbc::Mbc5, true, true, false, false),
+            0x1C => (Mbc::Mbc5, false, false, false, true),
+            0x1D => (Mbc::Mbc5, true, false, false, true),
+            0x1E => (Mbc::Mbc5, true, true, false, true),
+            _ => return None,
+        };
+
+        Some(CartridgeType { code, mbc, ram, battery, timer, rumble })
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CgbSupport {
+    None,
+    // 0x80: funciona tambien en DMG
+    Compatible,
+    // 0xC0
+    Only,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Destination {
+    Japan,
+    Overseas,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum HeaderError {
+    TooShort { len: usize },
+    UnsupportedCartridgeType(u8),
+    InvalidRomSize(u8),
+    InvalidRamSize(u8),
+    HeaderChecksumMismatch { expected: u8, computed: u8 },
+}
+
+impl fmt::Display for HeaderError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            HeaderError::TooShort { len } => write!(f, "ROM demasiado corta para tener cabecera ({} bytes)", len),
+            HeaderError::UnsupportedCartridgeType(code) => write!(f, "Tipo de cartucho no soportado: {:02X}", code),
+            HeaderError::InvalidRomSize(code) => write!(f, "Tamaño de ROM no valido: {:02X}", code),
+            HeaderError::InvalidRamSize(code) => write!(f, "Tamaño de RAM no valido: {:02X}", code),
+            HeaderError::HeaderChecksumMismatch { expected, computed } => {
+                write!(f, "Checksum de la cabecera erroneo: esperado {:02X}, calculado {:02X}", expected, computed)
+            },
+        }
+    }
+}
+
+impl std::error::Error for HeaderError {}
+
+// Errores al cargar una ROM
+#[derive(Debug)]
+pub enum LoadError {
+    Io(std::io::Error),
+    Truncated { expected: usize, actual: usize },
+    UnsupportedCartridgeType(u8),
+    ChecksumMismatch { expected: u8, computed: u8 },
+    InvalidHeader(HeaderError),
+}
+
+impl fmt::Display for LoadError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            LoadError::Io(e) => write!(f, "Error de lectura: {}", e),
+            LoadError::Truncated { expected, actual } => {
+                write!(f, "ROM incompleta: se esperaban {} bytes y hay {}", expected, actual)
+            },
+            LoadError::UnsupportedCartridgeType(code) => write!(f, "Tipo de cartucho no soportado: {:02X}", code),
+            LoadError::ChecksumMismatch { expected, computed } => {
+                write!(f, "Checksum de la cabecera erroneo: esperado {:02X}, calculado {:02X}", expected, computed)
+            },
+            LoadError::InvalidHeader(e) => write!(f, "Cabecera no valida: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for LoadError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            LoadError::Io(e) => Some(e),
+            LoadError::InvalidHeader(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<std::io::Error> for LoadError {
+    fn from(e: std::io::Error) -> Self {
+        LoadError::Io(e)
+    }
+}
+
+impl From<HeaderError> for LoadError {
+    fn from(e: HeaderError) -> Self {
+        match e {
+            HeaderError::TooShort { len } => LoadError::Truncated { expected: HEADER_END, actual: len },
+            HeaderError::UnsupportedCartridgeType(code) => LoadError::UnsupportedCartridgeType(code),
+            HeaderError::HeaderChecksumMismatch { expected, computed } => LoadError::ChecksumMismatch { expected, computed },
+            e => LoadError::InvalidHeader(e),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum HeaderWarning {
+    GlobalChecksumMismatch { expected: u16, computed: u16 },
+    RomSizeMismatch { declared: usize, actual: usize },
+    LogoMismatch,
+}
+
+impl fmt::Display for HeaderWarning {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            HeaderWarning::GlobalChecksumMismatch { expected, computed } => {
+                write!(f, "Checksum global erroneo: esperado {:04X}, calculado {:04X}", expected, computed)
+            },
+            HeaderWarning::RomSizeMismatch { declared, actual } => {
+                write!(f, "La cabecera indica {} bytes de ROM pero hay {}", declared, actual)
+            },
+            HeaderWarning::LogoMismatch => write!(f, "El logo de Nintendo no coincide"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CartridgeHeader {
+    pub title: String,
+    pub manufacturer_code: Option<String>,
+    pub cgb: CgbSupport,
+    pub new_licensee_code: String,
+    pub sgb: bool,
+    pub cartridge_type: CartridgeType,
+    pub rom_size: usize,
+    pub ram_size: usize,
+    pub destination: Destination,
+    pub old_licensee_code: u8,
+    pub version: u8,
+    pub header_checksum: u8,
+    pub global_checksum: u16,
+    pub warnings: Vec<HeaderWarning>,
+}
+
+impl CartridgeHeader {
+    pub fn parse(rom: &[u8]) -> Result<Self, HeaderError> {
+        if rom.len() < HEADER_END {
+            return Err(HeaderError::TooShort { len: rom.len() });
+        }
+
+        let computed_header_checksum = CartridgeHeader::compute_header_checksum(rom);
+        if computed_header_checksum != rom[0x014D] {
+            return Err(HeaderError::HeaderChecksumMismatch { expected: rom[0x014D], computed: computed_header_checksum });
+        }
+
+        let cartridge_type = CartridgeType::from_code(rom[0x0147])
+            .ok_or(HeaderError::UnsupportedCartridgeType(rom[0x0147]))?;
+
+        let rom_size = match rom[0x0148] {
+            code @ 0x00 ..= 0x08 => 0x8000 << code,
+            code => return Err(HeaderError::InvalidRomSize(code)),
+        };
+
+        // El MBC2 lleva la RAM dentro y la cabecera indica 0
+        let ram_size = match rom[0x0149] {
+            0x00 => 0,
+            0x01 => 0x800,
+            0x02 => 0x2000,
+            0x03 => 0x8000,
+            0x04 => 0x20000,
+            0x05 => 0x10000,
+            code => return Err(HeaderError::InvalidRamSize(code)),
+        };
+
+        let cgb = match rom[0x0143] {
+            0xC0 => CgbSupport::Only,
+            0x80 => CgbSupport::Compatible,
+            _ => CgbSupport::None,
+        };
+
+        // En los cartuchos de CGB el titulo es mas corto y puede llevar el codigo del fabricante
+        let (title_end, manufacturer_code) = if cgb == CgbSupport::None {
+            (0x0144, None)
+        } else {
+            let code = &rom[0x013F..0x0143];
+            if code.iter().all(|c| c.is_ascii_uppercase() || c.is_ascii_digit()) {
+                (0x013F, Some(String::from_utf8_lossy(code).into_owned()))
+            } else {
+                (0x0143, None)
+            }
+        };
+
+        let title = rom[0x0134..title_end]
+            .iter()
+            .take_while(|c| **c != 0x00)
+            .map(|c| if c.is_ascii_graphic() || *c == b' ' { *c as char } else { '?' })
+            .collect();
+
+        let header = CartridgeHeader {
+            title,
+            manufacturer_code,
+            cgb,
+            new_licensee_code: String::from_utf8_lossy(&rom[0x0144..0x0146]).into_owned(),
+            sgb: rom[0x0146] == 0x03,
+            cartridge_type,
+            rom_size,
+            ram_size,
+            destination: if rom[0x014A] == 0x00 { Destination::Japan } else { Destination::Overseas },
+            old_licensee_code: rom[0x014B],
+            version: rom[0x014C],
+            header_checksum: rom[0x014D],
+            global_checksum: (rom[0x014E] as u16) << 8 | rom[0x014F] as u16,
+            warnings: Vec::new(),
+        };
+
+        Ok(header.validate(rom))
+    }
+
+    // Comprobaciones que el hardware no hace, solo se avisan
+    fn validate(mut self, rom: &[u8]) -> Self {
+        if rom[0x0104..0x0134] != NINTENDO_LOGO {
+            self.warnings.push(HeaderWarning::LogoMismatch);
+        }
+
+        if rom.len() != self.rom_size {
+            self.warnings.push(HeaderWarning::RomSizeMismatch { declared: self.rom_size, actual: rom.len() });
+        }
+
+        let computed = CartridgeHeader::compute_global_checksum(rom);
+        if computed != self.global_checksum {
+            self.warnings.push(HeaderWarning::GlobalChecksumMismatch { expected: self.global_checksum, computed });
+        }
+
+        self
+    }
+
+    pub fn compute_header_checksum(rom: &[u8]) -> u8 {
+        rom[0x0134..=0x014C].iter().fold(0u8, |acc, b| acc.wrapping_sub(*b).wrapping_sub(1))
+    }
+
+    // Suma de todos los bytes de la ROM menos los dos del propio checksum
+    pub fn compute_global_checksum(rom: &[u8]) -> u16 {
+        rom.iter()
+            .enumerate()
+            .filter(|(i, _)| *i != 0x014E && *i != 0x014F)
+            .fold(0u16, |acc, (_, b)| acc.wrapping_add(*b as u16))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hardware::{bus::Bus, GameBoy};
+
+    // ROM de 32 KiB con logo y checksums correctos
+    fn rom() -> Vec<u8> {
+        let mut rom = vec![0x00; 0x8000];
+        rom[0x0104..0x0134].copy_from_slice(&NINTENDO_LOGO);
+        rom[0x0134..0x0138].copy_from_slice(b"TEST");
+        fix_checksums(&mut rom);
+        rom
+    }
+
+    fn fix_checksums(rom: &mut [u8]) {
+        rom[0x014D] = CartridgeHeader::compute_header_checksum(rom);
+        let global = CartridgeHeader::compute_global_checksum(rom);
+        rom[0x014E..0x0150].copy_from_slice(&global.to_be_bytes());
+    }
+
+    #[test]
+    fn valid_header() {
+        let header = CartridgeHeader::parse(&rom()).unwrap();
+
+        assert_eq!(header.title, "TEST");
+        assert_eq!(header.cartridge_type.mbc, Mbc::None);
+        assert_eq!(header.rom_size, 0x8000);
+        assert_eq!(header.ram_size, 0);
+        assert!(header.warnings.is_empty());
+    }
+
+    #[test]
+    fn header_checksum_mismatch() {
+        let mut rom = rom();
+        rom[0x014D] = rom[0x014D].wrapping_add(1);
+
+        assert_eq!(
+            CartridgeHeader::parse(&rom).unwrap_err(),
+            HeaderError::HeaderChecksumMismatch { expected: rom[0x014D], computed: rom[0x014D].wrapping_sub(1) }
+        );
+    }
+
+    #[test]
+    fn unsupported_cartridge_type() {
+        // MBC6
+        let mut rom = rom();
+        rom[0x0147] = 0x20;
+        fix_checksums(&mut rom);
+
+        assert_eq!(CartridgeHeader::parse(&rom).unwrap_err(), HeaderError::UnsupportedCartridgeType(0x20));
+        assert!(matches!(LoadError::from(HeaderError::UnsupportedCartridgeType(0x20)), LoadError::UnsupportedCartridgeType(0x20)));
+    }
+
+    #[test]
+    fn truncated_rom() {
+        let rom = rom();
+        assert_eq!(CartridgeHeader::parse(&rom[..0x0100]).unwrap_err(), HeaderError::TooShort { len: 0x0100 });
+
+        // La cabecera se puede leer pero faltan bancos: solo es un aviso al leer la cabecera
+        // y un error al cargar la ROM
+        let header = CartridgeHeader::parse(&rom[..0x4000]).unwrap();
+        assert!(header.warnings.contains(&HeaderWarning::RomSizeMismatch { declared: 0x8000, actual: 0x4000 }));
+
+        let mut gameboy = GameBoy::new(Bus::new(), false);
+        assert!(matches!(
+            gameboy.load_rom_from_bytes(&rom[..0x4000]),
+            Err(LoadError::Truncated { expected: 0x8000, actual: 0x4000 })
+        ));
+        assert!(matches!(gameboy.load_rom_from_bytes(&rom[..0x0100]), Err(LoadError::Truncated { .. })));
+    }
+}
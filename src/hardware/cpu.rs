@@ -11,6 +11,7 @@ const E: usize = 5;
 const H: usize = 6;
 const L: usize = 7;
 
+#[allow(clippy::upper_case_acronyms)]
 pub struct CPU {
     pub registers: [u8; 8],
     pub pc: u16,
@@ -27,8 +28,6 @@ pub struct CPU {
 
     pub op: u8,
 
-    div_timer: u32,
-
     inst_set: [fn(&mut CPU); 0x100],
     cb_set: [fn(&mut CPU); 0x100],
 }
@@ -39,7 +38,7 @@ impl CPU {
             registers: [0x00; 8],
             pc: 0x0000,
             sp: 0x0000,
-            bus: bus,
+            bus,
             cycles: 0,
             stop: false,
             halt: false,
@@ -49,7 +48,6 @@ impl CPU {
             ime: false,
 
             op: 0,
-            div_timer: 0,
 
             inst_set: [
 //              0x_0            0x_1            0x_2            0x_3            0x_4            0x_5            0x_6            0x_7            0x_8            0x_9            0x_A            0x_B            0x_C            0x_D            0x_E            0x_F        
@@ -124,9 +122,7 @@ impl CPU {
             self.decode_execute(op);
         }
 
-        self.update_div();
-
-        return self.cycles - cycles_temp;
+        self.cycles - cycles_temp
     }
 
     pub fn fetch(&mut self) -> u8 {
@@ -202,7 +198,7 @@ impl CPU {
                 return self.cycles - cycles_temp;
             }
         }
-        return self.cycles - cycles_temp;
+        self.cycles - cycles_temp
     }
 
     fn interrupt_handler(&mut self, int: usize) {
@@ -223,11 +219,4 @@ impl CPU {
         self.pc = 0x40 + int_offset[int];
         self.cycles += 4;
     }
-
-    fn update_div(&mut self) {
-        if (self.cycles / 256) as u32 > self.div_timer {
-            self.div_timer = (self.cycles / 256) as u32;
-            self.bus.increase_div();
-        }
-    }
 }
\ No newline at end of file
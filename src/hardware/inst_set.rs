@@ -676,7 +676,7 @@ pub fn daa(cpu: &mut CPU) {
     let mut a = cpu.registers[A];
     let mut adjust = if get_carry(cpu) != 0 { 0x60 } else { 0x00 };
     if get_half_carry(cpu) != 0 { adjust |= 0x06; };
-    if !(get_negative(cpu) != 0) {
+    if get_negative(cpu) == 0 {
         if a & 0x0F > 0x09 { adjust |= 0x06; };
         if a > 0x99 { adjust |= 0x60; };
         a = a.wrapping_add(adjust);
@@ -1317,7 +1317,7 @@ pub fn ld_hl_sp_i8(cpu: &mut CPU) {
 
     let x = src.wrapping_add(val);
     cpu.registers[L] = x as u8;
-    cpu.registers[H] = (x as u16 / 0x100) as u8;
+    cpu.registers[H] = (x / 0x100) as u8;
 
     set_flags(cpu, Z_FLAG | N_FLAG, false);
     set_flags(cpu, C_FLAG, (cpu.sp & 0x00FF) + (val & 0x00FF) > 0x00FF);
@@ -0,0 +1,88 @@
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Keys {
+    Down,
+    Up,
+    Left,
+    Right,
+    Start,
+    Select,
+    B,
+    A,
+}
+
+impl Keys {
+    // Bits 0-3 cruceta (Derecha, Izquierda, Arriba, Abajo), 4-7 botones (A, B, Select, Start)
+    fn mask(self) -> u8 {
+        match self {
+            Keys::Right => 0b00000001,
+            Keys::Left => 0b00000010,
+            Keys::Up => 0b00000100,
+            Keys::Down => 0b00001000,
+            Keys::A => 0b00010000,
+            Keys::B => 0b00100000,
+            Keys::Select => 0b01000000,
+            Keys::Start => 0b10000000,
+        }
+    }
+}
+
+// Registro P1/JOYP (0xFF00)
+pub struct Joypad {
+    // Un bit por boton, a 1 si esta pulsado
+    pressed: u8,
+    // Bits 4 (P14, cruceta) y 5 (P15, botones) de FF00, la fila se selecciona con un 0
+    select: u8,
+}
+
+impl Default for Joypad {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Joypad {
+    pub fn new() -> Self {
+        Joypad {
+            pressed: 0,
+            select: 0b00110000,
+        }
+    }
+
+    // Lineas P10 - P13, a 0 si hay algun boton pulsado en las filas seleccionadas
+    fn lines(&self) -> u8 {
+        let mut low = 0;
+
+        if self.select & 0b00010000 == 0 {
+            low |= self.pressed & 0x0F;
+        }
+        if self.select & 0b00100000 == 0 {
+            low |= self.pressed >> 4;
+        }
+
+        !low & 0x0F
+    }
+
+    pub fn read(&self) -> u8 {
+        0b11000000 | self.select | self.lines()
+    }
+
+    // Devuelve true si alguna linea pasa de 1 a 0 (interrupcion)
+    pub fn write(&mut self, val: u8) -> bool {
+        let before = self.lines();
+        self.select = val & 0b00110000;
+
+        before & !self.lines() != 0
+    }
+
+    pub fn set_key(&mut self, key: Keys, pressed: bool) -> bool {
+        let before = self.lines();
+
+        if pressed {
+            self.pressed |= key.mask();
+        } else {
+            self.pressed &= !key.mask();
+        }
+
+        before & !self.lines() != 0
+    }
+}
@@ -0,0 +1,118 @@
+use std::{cell::RefCell, rc::Rc};
+
+use super::{GameBoy, FRAME_CYCLES, serial::{SerialDevice, TRANSFER_CYCLES}};
+
+// Estado de cada extremo del cable, actualizado en cada SerialDevice::update
+#[derive(Default)]
+struct PortState {
+    sb: u8,
+    // Hay una transferencia con reloj externo esperando al otro extremo
+    waiting: bool,
+    // Byte que le ha enviado el otro extremo y ciclos hasta que termine de llegar
+    incoming: Option<(u8, u32)>,
+}
+
+#[derive(Default)]
+struct LinkState {
+    ports: [PortState; 2],
+}
+
+// Un extremo del cable. Los dos tienen que avanzar a la vez, ver LinkedGameBoys
+pub struct LinkPort {
+    state: Rc<RefCell<LinkState>>,
+    side: usize,
+}
+
+// Cable entre dos consolas del mismo proceso
+pub fn link_cable() -> (LinkPort, LinkPort) {
+    let state = Rc::new(RefCell::new(LinkState::default()));
+
+    (
+        LinkPort { state: state.clone(), side: 0 },
+        LinkPort { state, side: 1 },
+    )
+}
+
+impl SerialDevice for LinkPort {
+    fn transfer(&mut self, out: u8) -> u8 {
+        let mut state = self.state.borrow_mut();
+        let other = &mut state.ports[1 - self.side];
+
+        // Si el otro extremo no esta esperando con reloj externo no desplaza su registro
+        if !other.waiting || other.incoming.is_some() {
+            return 0xFF;
+        }
+
+        other.incoming = Some((out, TRANSFER_CYCLES));
+        other.sb
+    }
+
+    fn update(&mut self, cycles: u8, sb: u8, waiting: bool) -> Option<u8> {
+        let mut state = self.state.borrow_mut();
+        let port = &mut state.ports[self.side];
+
+        port.sb = sb;
+        port.waiting = waiting;
+
+        let (incoming, cycles_left) = port.incoming?;
+        if cycles_left > cycles as u32 {
+            port.incoming = Some((incoming, cycles_left - cycles as u32));
+            return None;
+        }
+
+        port.incoming = None;
+        Some(incoming)
+    }
+}
+
+// Dos consolas conectadas por el cable que se ejecutan intercalando instrucciones,
+// de forma que ninguna adelanta a la otra en mas de una instruccion
+pub struct LinkedGameBoys {
+    pub left: GameBoy,
+    pub right: GameBoy,
+    // Ciclos ejecutados por cada consola desde el inicio
+    cycles: [u64; 2],
+    // Ciclo en el que acaba el frame actual
+    frame_end: u64,
+}
+
+impl LinkedGameBoys {
+    // Sustituye los dispositivos serie que tuvieran las consolas
+    pub fn new(mut left: GameBoy, mut right: GameBoy) -> Self {
+        let (left_port, right_port) = link_cable();
+        left.set_serial_device(Box::new(left_port));
+        right.set_serial_device(Box::new(right_port));
+
+        LinkedGameBoys {
+            left,
+            right,
+            cycles: [0, 0],
+            frame_end: 0,
+        }
+    }
+
+    // Ejecuta un frame en las dos consolas
+    pub fn cycle(&mut self) {
+        self.frame_end += FRAME_CYCLES;
+
+        while self.cycles[0] < self.frame_end || self.cycles[1] < self.frame_end {
+            // Avanza la que va por detras
+            if self.cycles[0] <= self.cycles[1] {
+                self.cycles[0] += self.left.step();
+            } else {
+                self.cycles[1] += self.right.step();
+            }
+        }
+
+        self.left.end_frame();
+        self.right.end_frame();
+    }
+
+    // Separa las consolas, cada una sin nada conectado
+    pub fn unlink(mut self) -> (GameBoy, GameBoy) {
+        self.left.take_serial_device();
+        self.right.take_serial_device();
+
+        (self.left, self.right)
+    }
+}
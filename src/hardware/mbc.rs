@@ -1,24 +1,98 @@
+use std::{cell::Cell, rc::Rc, time::{SystemTime, UNIX_EPOCH}};
+
+use super::cartridge::CartridgeHeader;
+
 pub trait MbcController {
-    fn new<>(rom: &[u8]) -> Self where Self: Sized;
+    fn new<>(rom: &[u8], header: &CartridgeHeader) -> Self where Self: Sized;
     fn read(&self, dir: usize) -> u8;
     fn write(&mut self, dir: usize, val: u8);
+    fn read_ram(&self, dir: usize) -> u8;
+    // Devuelve true si el byte se ha guardado (RAM activada y presente, o registro del RTC)
+    fn write_ram(&mut self, dir: usize, val: u8) -> bool;
+    // Solo lo usan los cartuchos con reloj (MBC3)
+    fn set_time_source(&mut self, _time_source: Box<dyn TimeSource>) {}
+    // Estado del motor de vibracion (MBC5 con rumble)
+    fn rumble(&self) -> bool {
+        false
+    }
+    fn has_battery(&self) -> bool;
+    fn ram(&self) -> &[u8];
+    fn ram_mut(&mut self) -> &mut [u8];
+
+    // Contenido del fichero .sav
+    fn save_data(&self) -> Vec<u8> {
+        self.ram().to_vec()
+    }
+
+    fn load_save_data(&mut self, data: &[u8]) {
+        let ram = self.ram_mut();
+        let len = ram.len().min(data.len());
+        ram[..len].copy_from_slice(&data[..len]);
+    }
+}
+
+// Fuente de tiempo del RTC, en segundos
+pub trait TimeSource {
+    fn now(&self) -> u64;
+}
+
+pub struct SystemTimeSource;
+
+impl TimeSource for SystemTimeSource {
+    fn now(&self) -> u64 {
+        SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0)
+    }
+}
+
+// Reloj que solo avanza manualmente, para pruebas deterministas. Los clones comparten el tiempo.
+#[derive(Clone, Default)]
+pub struct ManualTimeSource {
+    secs: Rc<Cell<u64>>,
+}
+
+impl ManualTimeSource {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn advance(&self, secs: u64) {
+        self.secs.set(self.secs.get() + secs);
+    }
+}
+
+impl TimeSource for ManualTimeSource {
+    fn now(&self) -> u64 {
+        self.secs.get()
+    }
 }
 
 pub struct MBC0 {
     rom: Vec<u8>,
+    ram: Vec<u8>,
+    battery: bool,
 }
 
 pub struct MBC1 {
     rom: Vec<u8>,
+    ram: Vec<u8>,
+    // Registro de 5 bits (0x2000 - 0x3FFF)
     rom_bank_number: u8,
+    // Registro de 2 bits (0x4000 - 0x5FFF), bits altos del banco de ROM o banco de RAM
+    upper_bank_number: u8,
     ram_enable: bool,
     banking_mode: u8,
+    rom_bank_mask: usize,
+    // MBC1M: cartuchos multijuego donde el registro alto empieza en el bit 4
+    multicart: bool,
+    battery: bool,
 }
 
 impl MbcController for MBC0 {
-    fn new<>(rom: &[u8]) -> Self {
+    fn new<>(rom: &[u8], header: &CartridgeHeader) -> Self {
         MBC0 {
-            rom: rom.to_vec()
+            rom: rom.to_vec(),
+            ram: vec![0x00; header.ram_size],
+            battery: header.cartridge_type.battery,
         }
     }
 
@@ -26,49 +100,779 @@ impl MbcController for MBC0 {
         self.rom[dir]
     }
 
-    // TODO poner si hay ram optativa
     fn write(&mut self, _dir: usize, _val: u8) {}
+
+    fn read_ram(&self, dir: usize) -> u8 {
+        self.ram.get(dir - 0xA000).copied().unwrap_or(0xFF)
+    }
+
+    fn write_ram(&mut self, dir: usize, val: u8) -> bool {
+        match self.ram.get_mut(dir - 0xA000) {
+            Some(byte) => {
+                *byte = val;
+                true
+            },
+            None => false,
+        }
+    }
+
+    fn has_battery(&self) -> bool {
+        self.battery
+    }
+
+    fn ram(&self) -> &[u8] {
+        &self.ram
+    }
+
+    fn ram_mut(&mut self) -> &mut [u8] {
+        &mut self.ram
+    }
+}
+
+impl MBC1 {
+    fn is_multicart(rom: &[u8]) -> bool {
+        // Los MBC1M son de 1 MiB y tienen la cabecera de otro juego (con el logo de Nintendo)
+        // al principio del banco 0x10
+        rom.len() == 0x100000 && rom[0x0104..0x0134] == rom[0x40104..0x40134]
+    }
+
+    fn upper_bits(&self) -> usize {
+        let shift = if self.multicart { 4 } else { 5 };
+        (self.upper_bank_number as usize) << shift
+    }
+
+    fn bank_zero(&self) -> usize {
+        if self.banking_mode == 0 {
+            0
+        } else {
+            self.upper_bits() & self.rom_bank_mask
+        }
+    }
+
+    fn bank_high(&self) -> usize {
+        let lower = if self.multicart {
+            self.rom_bank_number & 0x0F
+        } else {
+            self.rom_bank_number
+        } as usize;
+
+        (self.upper_bits() | lower) & self.rom_bank_mask
+    }
+
+    fn ram_dir(&self, dir: usize) -> usize {
+        let bank = if self.banking_mode == 0 {
+            0
+        } else {
+            self.upper_bank_number as usize
+        };
+
+        (bank * 0x2000 + (dir - 0xA000)) % self.ram.len()
+    }
 }
 
 impl MbcController for MBC1 {
-    fn new<>(rom: &[u8]) -> Self {
+    fn new<>(rom: &[u8], header: &CartridgeHeader) -> Self {
+        let rom_banks = (rom.len() / 0x4000).max(2);
+
         MBC1 {
             rom: rom.to_vec(),
+            ram: vec![0x00; header.ram_size],
             rom_bank_number: 1,
+            upper_bank_number: 0,
             ram_enable: false,
             banking_mode: 0,
+            rom_bank_mask: rom_banks.next_power_of_two() - 1,
+            multicart: MBC1::is_multicart(rom),
+            battery: header.cartridge_type.battery,
         }
     }
 
     fn read(&self, dir: usize) -> u8 {
-        if self.banking_mode == 0 {
-            if dir <= 0x3FFF {
-                self.rom[dir]
-            } else {
-                self.rom[(dir - 0x4000) + 0x4000 * self.rom_bank_number as usize]
-            }
+        let bank = if dir <= 0x3FFF {
+            self.bank_zero()
         } else {
-            // TODO BANK Mode 1
-            0
-        }
+            self.bank_high()
+        };
+
+        self.rom[(bank * 0x4000 + (dir & 0x3FFF)) % self.rom.len()]
     }
 
     fn write(&mut self, dir: usize, val: u8) {
         if dir < 0x2000 {
-            if val == 0x0A { self.ram_enable = true } else { self.ram_enable = false };
+            self.ram_enable = val & 0x0F == 0x0A;
         } else if dir < 0x4000 {
-            // TODO Añadir caso de que el cartucho sea grande
+            // El banco 0 se convierte en 1 antes de aplicar la mascara
             self.rom_bank_number = val & 0b00011111;
 
-            // TODO self.mask_rom_number_to_size();
+            if self.rom_bank_number == 0 {
+                self.rom_bank_number = 1;
+            }
+        } else if dir < 0x6000 {
+            self.upper_bank_number = val & 0b00000011;
+        } else {
+            self.banking_mode = val & 0b00000001;
+        }
+    }
+
+    fn read_ram(&self, dir: usize) -> u8 {
+        if !self.ram_enable || self.ram.is_empty() {
+            return 0xFF;
+        }
+
+        self.ram[self.ram_dir(dir)]
+    }
+
+    fn write_ram(&mut self, dir: usize, val: u8) -> bool {
+        if !self.ram_enable || self.ram.is_empty() {
+            return false;
+        }
+
+        let index = self.ram_dir(dir);
+        self.ram[index] = val;
+        true
+    }
+
+    fn has_battery(&self) -> bool {
+        self.battery
+    }
+
+    fn ram(&self) -> &[u8] {
+        &self.ram
+    }
+
+    fn ram_mut(&mut self) -> &mut [u8] {
+        &mut self.ram
+    }
+}
+
+// Registros del RTC del MBC3
+const RTC_S: usize = 0;
+const RTC_M: usize = 1;
+const RTC_H: usize = 2;
+const RTC_DL: usize = 3;
+const RTC_DH: usize = 4;
+// Tamaño del estado del RTC en el fichero .sav
+const RTC_SAVE_SIZE: usize = 48;
+// Bits usados de cada registro del RTC
+const RTC_MASKS: [u8; 5] = [0b00111111, 0b00111111, 0b00011111, 0b11111111, 0b11000001];
+
+struct Rtc {
+    // Segundos, minutos, horas, dias (bits bajos), dias (bit alto) + halt + carry
+    regs: [u8; 5],
+    latched: [u8; 5],
+    last_time: u64,
+    time_source: Box<dyn TimeSource>,
+}
+
+impl Rtc {
+    fn new(time_source: Box<dyn TimeSource>) -> Self {
+        Rtc {
+            regs: [0x00; 5],
+            latched: [0x00; 5],
+            last_time: time_source.now(),
+            time_source,
+        }
+    }
+
+    fn halted(&self) -> bool {
+        self.regs[RTC_DH] & 0b01000000 != 0
+    }
+
+    fn update(&mut self) {
+        let now = self.time_source.now();
+        let elapsed = now.saturating_sub(self.last_time);
+        self.last_time = now;
+
+        if self.halted() || elapsed == 0 {
+            return;
+        }
+
+        let seconds = self.regs[RTC_S] as u64 + elapsed;
+        let minutes = self.regs[RTC_M] as u64 + seconds / 60;
+        let hours = self.regs[RTC_H] as u64 + minutes / 60;
+        let days = (self.regs[RTC_DL] as u64 | ((self.regs[RTC_DH] as u64 & 0x01) << 8)) + hours / 24;
+
+        self.regs[RTC_S] = (seconds % 60) as u8;
+        self.regs[RTC_M] = (minutes % 60) as u8;
+        self.regs[RTC_H] = (hours % 24) as u8;
+        self.regs[RTC_DL] = days as u8;
+
+        // El bit de carry se queda activo hasta que el juego lo borra
+        let carry = if days > 0x1FF { 0b10000000 } else { 0x00 };
+        self.regs[RTC_DH] = (self.regs[RTC_DH] & 0b11000000) | carry | ((days >> 8) & 0x01) as u8;
+    }
+
+    fn save_data(&self) -> Vec<u8> {
+        let mut data = Vec::with_capacity(RTC_SAVE_SIZE);
+
+        for reg in self.regs.iter().chain(self.latched.iter()) {
+            data.extend((*reg as u32).to_le_bytes());
+        }
+        data.extend(self.last_time.to_le_bytes());
+
+        data
+    }
+
+    fn load_save_data(&mut self, data: &[u8]) {
+        if data.len() < RTC_SAVE_SIZE {
+            return;
+        }
+
+        for i in 0..5 {
+            self.regs[i] = data[i * 4] & RTC_MASKS[i];
+            self.latched[i] = data[(i + 5) * 4] & RTC_MASKS[i];
+        }
+
+        // El reloj sigue avanzando mientras el emulador esta cerrado
+        let mut timestamp = [0x00; 8];
+        timestamp.copy_from_slice(&data[40..48]);
+        self.last_time = u64::from_le_bytes(timestamp);
+        self.update();
+    }
+
+    fn latch(&mut self) {
+        self.update();
+        self.latched = self.regs;
+    }
+
+    fn read(&self, reg: usize) -> u8 {
+        // Los bits no usados se leen a 1
+        self.latched[reg] | !RTC_MASKS[reg]
+    }
+
+    fn write(&mut self, reg: usize, val: u8) {
+        self.update();
+        self.regs[reg] = val & RTC_MASKS[reg];
+    }
+}
+
+pub struct MBC3 {
+    rom: Vec<u8>,
+    ram: Vec<u8>,
+    rom_bank_number: u8,
+    // 0x00 - 0x03 bancos de RAM, 0x08 - 0x0C registros del RTC
+    ram_bank_number: u8,
+    ram_enable: bool,
+    last_latch_write: u8,
+    rtc: Option<Rtc>,
+    battery: bool,
+}
+
+impl MBC3 {
+    pub fn with_time_source(rom: &[u8], header: &CartridgeHeader, time_source: Box<dyn TimeSource>) -> Self {
+        let has_rtc = header.cartridge_type.timer;
+
+        MBC3 {
+            rom: rom.to_vec(),
+            ram: vec![0x00; header.ram_size],
+            rom_bank_number: 1,
+            ram_bank_number: 0,
+            ram_enable: false,
+            last_latch_write: 0xFF,
+            rtc: if has_rtc { Some(Rtc::new(time_source)) } else { None },
+            battery: header.cartridge_type.battery,
+        }
+    }
+
+    fn ram_dir(&self, dir: usize) -> usize {
+        (self.ram_bank_number as usize * 0x2000 + (dir - 0xA000)) % self.ram.len()
+    }
+}
+
+impl MbcController for MBC3 {
+    fn new<>(rom: &[u8], header: &CartridgeHeader) -> Self {
+        MBC3::with_time_source(rom, header, Box::new(SystemTimeSource))
+    }
+
+    fn read(&self, dir: usize) -> u8 {
+        if dir <= 0x3FFF {
+            self.rom[dir]
+        } else {
+            self.rom[((dir - 0x4000) + 0x4000 * self.rom_bank_number as usize) % self.rom.len()]
+        }
+    }
+
+    fn write(&mut self, dir: usize, val: u8) {
+        if dir < 0x2000 {
+            self.ram_enable = val & 0x0F == 0x0A;
+        } else if dir < 0x4000 {
+            self.rom_bank_number = val & 0b01111111;
 
             if self.rom_bank_number == 0 {
                 self.rom_bank_number = 1;
             }
         } else if dir < 0x6000 {
-            
+            self.ram_bank_number = val;
+        } else {
+            // Escribir 0x00 y despues 0x01 copia el reloj en los registros latch
+            if self.last_latch_write == 0x00 && val == 0x01 {
+                if let Some(rtc) = self.rtc.as_mut() {
+                    rtc.latch();
+                }
+            }
+            self.last_latch_write = val;
+        }
+    }
+
+    fn read_ram(&self, dir: usize) -> u8 {
+        if !self.ram_enable {
+            return 0xFF;
+        }
+
+        match (self.ram_bank_number, self.rtc.as_ref()) {
+            (0x00 ..= 0x03, _) if !self.ram.is_empty() => self.ram[self.ram_dir(dir)],
+            (0x08 ..= 0x0C, Some(rtc)) => rtc.read(self.ram_bank_number as usize - 0x08),
+            _ => 0xFF,
+        }
+    }
+
+    fn write_ram(&mut self, dir: usize, val: u8) -> bool {
+        if !self.ram_enable {
+            return false;
+        }
+
+        match (self.ram_bank_number, self.rtc.as_mut()) {
+            (0x00 ..= 0x03, _) if !self.ram.is_empty() => {
+                let index = self.ram_dir(dir);
+                self.ram[index] = val;
+            },
+            (0x08 ..= 0x0C, Some(rtc)) => rtc.write(self.ram_bank_number as usize - 0x08, val),
+            _ => return false,
+        }
+
+        true
+    }
+
+    fn set_time_source(&mut self, time_source: Box<dyn TimeSource>) {
+        if let Some(rtc) = self.rtc.as_mut() {
+            rtc.update();
+            rtc.last_time = time_source.now();
+            rtc.time_source = time_source;
+        }
+    }
+
+    fn has_battery(&self) -> bool {
+        self.battery
+    }
+
+    fn ram(&self) -> &[u8] {
+        &self.ram
+    }
+
+    fn ram_mut(&mut self) -> &mut [u8] {
+        &mut self.ram
+    }
+
+    // Si hay reloj se guarda detras de la RAM con el formato de 48 bytes de BGB/VBA
+    fn save_data(&self) -> Vec<u8> {
+        let mut data = self.ram.clone();
+
+        if let Some(rtc) = self.rtc.as_ref() {
+            data.extend(rtc.save_data());
+        }
+
+        data
+    }
+
+    fn load_save_data(&mut self, data: &[u8]) {
+        let len = self.ram.len().min(data.len());
+        self.ram[..len].copy_from_slice(&data[..len]);
+
+        if let (Some(rtc), Some(rtc_data)) = (self.rtc.as_mut(), data.get(self.ram.len()..)) {
+            rtc.load_save_data(rtc_data);
+        }
+    }
+}
+
+pub struct MBC5 {
+    rom: Vec<u8>,
+    ram: Vec<u8>,
+    // 9 bits, el banco 0 tambien se puede seleccionar
+    rom_bank_number: u16,
+    ram_bank_number: u8,
+    ram_enable: bool,
+    // En los cartuchos con vibracion el bit 3 del banco de RAM controla el motor
+    has_rumble: bool,
+    rumble: bool,
+    battery: bool,
+}
+
+impl MBC5 {
+    fn ram_dir(&self, dir: usize) -> usize {
+        (self.ram_bank_number as usize * 0x2000 + (dir - 0xA000)) % self.ram.len()
+    }
+}
+
+impl MbcController for MBC5 {
+    fn new<>(rom: &[u8], header: &CartridgeHeader) -> Self {
+        MBC5 {
+            rom: rom.to_vec(),
+            ram: vec![0x00; header.ram_size],
+            rom_bank_number: 1,
+            ram_bank_number: 0,
+            ram_enable: false,
+            has_rumble: header.cartridge_type.rumble,
+            rumble: false,
+            battery: header.cartridge_type.battery,
+        }
+    }
+
+    fn read(&self, dir: usize) -> u8 {
+        if dir <= 0x3FFF {
+            self.rom[dir]
+        } else {
+            self.rom[((dir - 0x4000) + 0x4000 * self.rom_bank_number as usize) % self.rom.len()]
+        }
+    }
+
+    fn write(&mut self, dir: usize, val: u8) {
+        if dir < 0x2000 {
+            self.ram_enable = val & 0x0F == 0x0A;
+        } else if dir < 0x3000 {
+            self.rom_bank_number = (self.rom_bank_number & 0x100) | val as u16;
+        } else if dir < 0x4000 {
+            self.rom_bank_number = (self.rom_bank_number & 0xFF) | ((val as u16 & 0x01) << 8);
+        } else if dir < 0x6000 {
+            if self.has_rumble {
+                self.rumble = val & 0b00001000 != 0;
+                self.ram_bank_number = val & 0b00000111;
+            } else {
+                self.ram_bank_number = val & 0b00001111;
+            }
+        }
+    }
+
+    fn read_ram(&self, dir: usize) -> u8 {
+        if !self.ram_enable || self.ram.is_empty() {
+            return 0xFF;
+        }
+
+        self.ram[self.ram_dir(dir)]
+    }
+
+    fn write_ram(&mut self, dir: usize, val: u8) -> bool {
+        if !self.ram_enable || self.ram.is_empty() {
+            return false;
+        }
+
+        let index = self.ram_dir(dir);
+        self.ram[index] = val;
+        true
+    }
+
+    fn has_battery(&self) -> bool {
+        self.battery
+    }
+
+    fn ram(&self) -> &[u8] {
+        &self.ram
+    }
+
+    fn ram_mut(&mut self) -> &mut [u8] {
+        &mut self.ram
+    }
+
+    fn rumble(&self) -> bool {
+        self.rumble
+    }
+}
+
+pub struct MBC2 {
+    rom: Vec<u8>,
+    // 512 x 4 bits, se guarda un nibble por byte
+    ram: [u8; 0x200],
+    rom_bank_number: u8,
+    ram_enable: bool,
+    battery: bool,
+}
+
+impl MbcController for MBC2 {
+    fn new<>(rom: &[u8], header: &CartridgeHeader) -> Self {
+        MBC2 {
+            rom: rom.to_vec(),
+            ram: [0x00; 0x200],
+            rom_bank_number: 1,
+            ram_enable: false,
+            battery: header.cartridge_type.battery,
+        }
+    }
+
+    fn read(&self, dir: usize) -> u8 {
+        if dir <= 0x3FFF {
+            self.rom[dir]
+        } else {
+            self.rom[((dir - 0x4000) + 0x4000 * self.rom_bank_number as usize) % self.rom.len()]
+        }
+    }
+
+    fn write(&mut self, dir: usize, val: u8) {
+        if dir >= 0x4000 {
+            return;
+        }
+
+        // El bit 8 de la direccion selecciona el registro
+        if dir & 0x0100 == 0 {
+            self.ram_enable = val & 0x0F == 0x0A;
         } else {
-            self.banking_mode = val;
+            self.rom_bank_number = val & 0x0F;
+
+            if self.rom_bank_number == 0 {
+                self.rom_bank_number = 1;
+            }
+        }
+    }
+
+    fn read_ram(&self, dir: usize) -> u8 {
+        if !self.ram_enable {
+            return 0xFF;
+        }
+
+        // Solo hay 512 posiciones, repetidas en todo 0xA000 - 0xBFFF. El nibble alto se lee a 1.
+        self.ram[(dir - 0xA000) & 0x01FF] | 0xF0
+    }
+
+    fn write_ram(&mut self, dir: usize, val: u8) -> bool {
+        if self.ram_enable {
+            self.ram[(dir - 0xA000) & 0x01FF] = val & 0x0F;
+        }
+
+        self.ram_enable
+    }
+
+    fn has_battery(&self) -> bool {
+        self.battery
+    }
+
+    fn ram(&self) -> &[u8] {
+        &self.ram
+    }
+
+    fn ram_mut(&mut self) -> &mut [u8] {
+        &mut self.ram
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // MBC1 con un byte al principio de cada banco que indica su numero
+    fn mbc1_rom(rom_size_code: u8) -> Vec<u8> {
+        let mut rom = vec![0x00; 0x8000 << rom_size_code];
+        for bank in 0..rom.len() / 0x4000 {
+            rom[bank * 0x4000 + 0x10] = bank as u8;
         }
+        // Sin logo en el banco 0x10 para que no parezca un MBC1M
+        rom[0x0104] = 0xCE;
+        rom[0x0147] = 0x01;
+        rom[0x0148] = rom_size_code;
+        rom[0x014D] = CartridgeHeader::compute_header_checksum(&rom);
+        rom
+    }
+
+    fn mbc1(rom: &[u8]) -> MBC1 {
+        MBC1::new(rom, &CartridgeHeader::parse(rom).unwrap())
+    }
+
+    #[test]
+    fn mbc1_bank_zero_selects_one() {
+        let mut mbc = mbc1(&mbc1_rom(0x03));
+
+        mbc.write(0x2000, 0x00);
+        assert_eq!(mbc.read(0x4010), 1);
+
+        // Solo se miran 5 bits, 0x20 tambien es el banco 0
+        mbc.write(0x2000, 0x20);
+        assert_eq!(mbc.read(0x4010), 1);
+
+        mbc.write(0x2000, 0x05);
+        assert_eq!(mbc.read(0x4010), 5);
+    }
+
+    #[test]
+    fn mbc1_bank_mask() {
+        // 256 KiB, 16 bancos
+        let mut mbc = mbc1(&mbc1_rom(0x03));
+
+        mbc.write(0x2000, 0x12);
+        assert_eq!(mbc.read(0x4010), 0x02);
+
+        // El 0x10 pasa a 1 antes de la mascara, pero al enmascarar queda el 0
+        mbc.write(0x2000, 0x10);
+        assert_eq!(mbc.read(0x4010), 0x00);
+
+        // Los bits altos tampoco existen en una ROM de este tamaño
+        mbc.write(0x2000, 0x03);
+        mbc.write(0x4000, 0x01);
+        assert_eq!(mbc.read(0x4010), 0x03);
+    }
+
+    #[test]
+    fn mbc1_mode_1_bank_zero() {
+        // 1 MiB, 64 bancos
+        let mut mbc = mbc1(&mbc1_rom(0x05));
+        assert!(!mbc.multicart);
+
+        mbc.write(0x4000, 0x01);
+        mbc.write(0x2000, 0x02);
+        assert_eq!(mbc.read(0x0010), 0x00);
+        assert_eq!(mbc.read(0x4010), 0x22);
+
+        // En modo 1 el registro alto tambien se aplica a 0x0000 - 0x3FFF
+        mbc.write(0x6000, 0x01);
+        assert_eq!(mbc.read(0x0010), 0x20);
+        assert_eq!(mbc.read(0x4010), 0x22);
+
+        mbc.write(0x6000, 0x00);
+        assert_eq!(mbc.read(0x0010), 0x00);
+    }
+
+    #[test]
+    fn mbc1m() {
+        let mut rom = mbc1_rom(0x05);
+        // La cabecera del segundo juego empieza en el banco 0x10
+        let header = rom[0x0100..0x0150].to_vec();
+        rom[0x40100..0x40150].copy_from_slice(&header);
+
+        let mut mbc = mbc1(&rom);
+        assert!(mbc.multicart);
+
+        // El registro alto va en los bits 4 - 5 y del bajo solo se usan 4 bits
+        mbc.write(0x4000, 0x01);
+        mbc.write(0x2000, 0x13);
+        assert_eq!(mbc.read(0x4010), 0x13);
+
+        mbc.write(0x6000, 0x01);
+        assert_eq!(mbc.read(0x0010), 0x10);
+    }
+
+    // MBC3 + TIMER + RAM + BATTERY con 32 KiB de RAM
+    fn mbc3_rtc() -> (MBC3, ManualTimeSource) {
+        let mut rom = vec![0x00; 0x8000];
+        rom[0x0147] = 0x10;
+        rom[0x0149] = 0x03;
+        rom[0x014D] = CartridgeHeader::compute_header_checksum(&rom);
+        let header = CartridgeHeader::parse(&rom).unwrap();
+
+        let time = ManualTimeSource::new();
+        let mut mbc = MBC3::with_time_source(&rom, &header, Box::new(time.clone()));
+        mbc.write(0x0000, 0x0A);
+
+        (mbc, time)
+    }
+
+    fn latch(mbc: &mut MBC3) {
+        mbc.write(0x6000, 0x00);
+        mbc.write(0x6000, 0x01);
+    }
+
+    fn read_rtc(mbc: &mut MBC3, reg: usize) -> u8 {
+        mbc.write(0x4000, 0x08 + reg as u8);
+        mbc.read_ram(0xA000) & RTC_MASKS[reg]
+    }
+
+    fn write_rtc(mbc: &mut MBC3, reg: usize, val: u8) {
+        mbc.write(0x4000, 0x08 + reg as u8);
+        mbc.write_ram(0xA000, val);
+    }
+
+    #[test]
+    fn latch_sequence() {
+        let (mut mbc, time) = mbc3_rtc();
+
+        time.advance(5);
+        assert_eq!(read_rtc(&mut mbc, RTC_S), 0);
+        latch(&mut mbc);
+        assert_eq!(read_rtc(&mut mbc, RTC_S), 5);
+
+        // Los registros latch no cambian hasta el siguiente 0x00, 0x01
+        time.advance(10);
+        assert_eq!(read_rtc(&mut mbc, RTC_S), 5);
+        mbc.write(0x6000, 0x01);
+        assert_eq!(read_rtc(&mut mbc, RTC_S), 5);
+        latch(&mut mbc);
+        assert_eq!(read_rtc(&mut mbc, RTC_S), 15);
+    }
+
+    #[test]
+    fn rollover() {
+        let (mut mbc, time) = mbc3_rtc();
+
+        write_rtc(&mut mbc, RTC_S, 59);
+        write_rtc(&mut mbc, RTC_M, 59);
+        write_rtc(&mut mbc, RTC_H, 23);
+        time.advance(1);
+        latch(&mut mbc);
+
+        assert_eq!(read_rtc(&mut mbc, RTC_S), 0);
+        assert_eq!(read_rtc(&mut mbc, RTC_M), 0);
+        assert_eq!(read_rtc(&mut mbc, RTC_H), 0);
+        assert_eq!(read_rtc(&mut mbc, RTC_DL), 1);
+        assert_eq!(read_rtc(&mut mbc, RTC_DH), 0);
+    }
+
+    #[test]
+    fn day_carry() {
+        let (mut mbc, time) = mbc3_rtc();
+
+        // Dia 511
+        write_rtc(&mut mbc, RTC_DL, 0xFF);
+        write_rtc(&mut mbc, RTC_DH, 0x01);
+        time.advance(24 * 60 * 60);
+        latch(&mut mbc);
+
+        assert_eq!(read_rtc(&mut mbc, RTC_DL), 0);
+        assert_eq!(read_rtc(&mut mbc, RTC_DH), 0b10000000);
+
+        // El carry se mantiene hasta que se borra
+        time.advance(24 * 60 * 60);
+        latch(&mut mbc);
+        assert_eq!(read_rtc(&mut mbc, RTC_DL), 1);
+        assert_eq!(read_rtc(&mut mbc, RTC_DH), 0b10000000);
+    }
+
+    #[test]
+    fn halt() {
+        let (mut mbc, time) = mbc3_rtc();
+
+        write_rtc(&mut mbc, RTC_DH, 0b01000000);
+        time.advance(100);
+        latch(&mut mbc);
+        assert_eq!(read_rtc(&mut mbc, RTC_S), 0);
+
+        write_rtc(&mut mbc, RTC_DH, 0x00);
+        time.advance(3);
+        latch(&mut mbc);
+        assert_eq!(read_rtc(&mut mbc, RTC_S), 3);
+    }
+
+    #[test]
+    fn save_and_reload() {
+        let (mut mbc, time) = mbc3_rtc();
+
+        mbc.write(0x4000, 0x00);
+        mbc.write_ram(0xA123, 0x42);
+        write_rtc(&mut mbc, RTC_M, 10);
+        time.advance(30);
+        latch(&mut mbc);
+
+        let data = mbc.save_data();
+        assert_eq!(data.len(), 0x8000 + RTC_SAVE_SIZE);
+
+        // El reloj sigue avanzando con el juego cerrado
+        time.advance(60);
+        let (mut loaded, _) = mbc3_rtc();
+        loaded.set_time_source(Box::new(time.clone()));
+        loaded.load_save_data(&data);
+
+        loaded.write(0x4000, 0x00);
+        assert_eq!(loaded.read_ram(0xA123), 0x42);
+        // Los registros latch tambien se recuperan
+        assert_eq!(read_rtc(&mut loaded, RTC_S), 30);
+        assert_eq!(read_rtc(&mut loaded, RTC_M), 10);
+
+        latch(&mut loaded);
+        assert_eq!(read_rtc(&mut loaded, RTC_S), 30);
+        assert_eq!(read_rtc(&mut loaded, RTC_M), 11);
     }
 }
@@ -1,47 +1,185 @@
-use sfml::graphics::{Sprite, Texture, Transformable, RenderWindow, RenderTarget};
+use std::{collections::VecDeque, path::{Path, PathBuf}};
 
-use self::{cpu::CPU, bus::{Bus, Interrupts}, mbc::*};
+use self::{cpu::CPU, bus::Bus, mbc::*, cartridge::{CartridgeHeader, Mbc}, audio::{Resampler, UnsupportedSampleRate}};
+
+pub use self::cartridge::LoadError;
+pub use self::audio::AudioSink;
+pub use self::joypad::Keys;
+pub use self::serial::SerialDevice;
 
 pub mod cpu;
 mod ppu;
 mod inst_set;
-mod mbc;
+pub mod mbc;
 pub mod bus;
+pub mod cartridge;
+pub mod apu;
+pub mod audio;
+pub mod wav;
+mod timer;
+mod joypad;
+pub mod serial;
+pub mod link;
+pub mod socket_link;
+pub mod printer;
+
+pub use self::ppu::{SCREEN_WIDTH, SCREEN_HEIGHT};
+
+// Ciclos de un frame (154 lineas de 456 ciclos)
+pub const FRAME_CYCLES: u64 = 70224;
+
+// Frames que se espera desde la primera escritura en la RAM del cartucho hasta guardar el .sav
+const SAVE_INTERVAL_FRAMES: u32 = 300;
+
+// Eventos que se guardan sin que el frontend los recoja, se descartan los mas antiguos
+const MAX_EVENTS: usize = 64;
+
+// Eventos que el frontend puede recoger con GameBoy::poll_event
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum GameBoyEvent {
+    // Motor de vibracion del cartucho encendido o apagado
+    Rumble(bool),
+    // No se ha podido escribir el .sav, con la descripcion del error. La RAM sigue marcada como modificada
+    SaveFailed(String),
+    // Error del dispositivo conectado al puerto serie, por ejemplo al guardar una impresion
+    SerialDeviceFailed(String),
+}
 
-pub enum Keys {
-    Down,
-    Up,
-    Left,
-    Right,
-    Start,
-    Select,
-    B,
-    A,
+struct AudioOutput {
+    sink: Box<dyn AudioSink>,
+    resampler: Resampler,
+    buffer: Vec<f32>,
 }
 
 pub struct GameBoy {
     pub cpu: CPU,
     enable_boot_rom: bool,
+
+    events: VecDeque<GameBoyEvent>,
+    rumble: bool,
+    // El motor ha estado encendido en algun momento del frame actual
+    rumble_frame: bool,
+
+    header: Option<CartridgeHeader>,
+    // Fichero .sav del cartucho cargado, solo si tiene pila
+    save_path: Option<PathBuf>,
+    frames_ram_dirty: u32,
+
+    audio: Option<AudioOutput>,
 }
 
 impl GameBoy {
     pub fn new(bus: Bus, enable_boot_rom: bool) -> Self {
         GameBoy {
             cpu: CPU::new(bus.set_enable_boot_rom(enable_boot_rom)),
-            enable_boot_rom: enable_boot_rom,
+            enable_boot_rom,
+
+            events: VecDeque::new(),
+            rumble: false,
+            rumble_frame: false,
+
+            header: None,
+            save_path: None,
+            frames_ram_dirty: 0,
+
+            audio: None,
         }   
     }
 
-    pub fn load_rom(&mut self, dir: &str) {
-        let rom = std::fs::read(dir).unwrap();
+    pub fn load_rom(&mut self, dir: &str) -> Result<(), LoadError> {
+        let rom = std::fs::read(dir)?;
+
+        self.load_rom_from_bytes(&rom)?;
 
-        let mbc = rom[0x0147];
+        if self.cpu.bus.battery_save_data().is_some() {
+            let save_path = Path::new(dir).with_extension("sav");
 
-        match mbc {
-            0x00 => self.cpu.bus.set_rom(Some(Box::new(MBC0::new(&rom)))),
-            0x01 => self.cpu.bus.set_rom(Some(Box::new(MBC0::new(&rom)))),
-            _ => panic!("MBC Erroneo o no implementado."),
+            if let Ok(data) = std::fs::read(&save_path) {
+                self.cpu.bus.load_battery_save_data(&data);
+            }
+            self.save_path = Some(save_path);
         }
+
+        Ok(())
+    }
+
+    // Carga sin fichero .sav asociado
+    pub fn load_rom_from_bytes(&mut self, rom: &[u8]) -> Result<(), LoadError> {
+        let header = CartridgeHeader::parse(rom)?;
+
+        if rom.len() < header.rom_size {
+            return Err(LoadError::Truncated { expected: header.rom_size, actual: rom.len() });
+        }
+
+        // La partida del cartucho anterior se pierde si no se puede guardar, el frontend decide que hacer
+        if let Err(e) = self.save_ram() {
+            self.push_event(GameBoyEvent::SaveFailed(e.to_string()));
+        }
+
+        match header.cartridge_type.mbc {
+            Mbc::None => self.cpu.bus.set_rom(Some(Box::new(MBC0::new(rom, &header)))),
+            Mbc::Mbc1 => self.cpu.bus.set_rom(Some(Box::new(MBC1::new(rom, &header)))),
+            Mbc::Mbc2 => self.cpu.bus.set_rom(Some(Box::new(MBC2::new(rom, &header)))),
+            Mbc::Mbc3 => self.cpu.bus.set_rom(Some(Box::new(MBC3::new(rom, &header)))),
+            Mbc::Mbc5 => self.cpu.bus.set_rom(Some(Box::new(MBC5::new(rom, &header)))),
+        }
+        self.header = Some(header);
+        self.save_path = None;
+        self.cpu.bus.clear_ram_dirty();
+
+        Ok(())
+    }
+
+    // Cabecera del cartucho cargado
+    pub fn header(&self) -> Option<&CartridgeHeader> {
+        self.header.as_ref()
+    }
+
+    // Escribe la RAM del cartucho en el .sav si ha cambiado. Los frontends tienen que llamarlo antes de cerrar
+    // para poder informar del error, Drop lo vuelve a intentar pero solo puede escribirlo en stderr
+    pub fn save_ram(&mut self) -> std::io::Result<()> {
+        if !self.cpu.bus.ram_dirty() {
+            return Ok(());
+        }
+
+        if let (Some(save_path), Some(data)) = (self.save_path.as_ref(), self.cpu.bus.battery_save_data()) {
+            std::fs::write(save_path, data)?;
+        }
+
+        self.cpu.bus.clear_ram_dirty();
+        self.frames_ram_dirty = 0;
+        Ok(())
+    }
+
+    fn update_save(&mut self) {
+        if !self.cpu.bus.ram_dirty() {
+            return;
+        }
+
+        self.frames_ram_dirty += 1;
+        if self.frames_ram_dirty >= SAVE_INTERVAL_FRAMES {
+            if let Err(e) = self.save_ram() {
+                // Se vuelve a intentar pasado otro intervalo
+                self.frames_ram_dirty = 0;
+                self.push_event(GameBoyEvent::SaveFailed(e.to_string()));
+            }
+        }
+    }
+
+    // Conecta un dispositivo al puerto serie, sustituyendo al que hubiera
+    pub fn set_serial_device(&mut self, device: Box<dyn SerialDevice>) {
+        self.cpu.bus.set_serial_device(Some(device));
+    }
+
+    // Desconecta el dispositivo del puerto serie. Antes de soltarlo hay que llamar a SerialDevice::finish
+    // para no perder lo que tenga pendiente
+    pub fn take_serial_device(&mut self) -> Option<Box<dyn SerialDevice>> {
+        self.cpu.bus.set_serial_device(None)
+    }
+
+    // Cambia la fuente de tiempo del RTC del cartucho cargado, si tiene
+    pub fn set_rtc_time_source(&mut self, time_source: Box<dyn TimeSource>) {
+        self.cpu.bus.set_time_source(time_source);
     }
 
     pub fn reset(&mut self) {
@@ -51,79 +189,133 @@ impl GameBoy {
     pub fn cycle(&mut self) {
         let mut cycles = 0;
         
-        while cycles < 70224 {
-            let mut cycles_to_run = self.cpu.interrupt();
+        while cycles < FRAME_CYCLES {
+            cycles += self.step();
+        };
 
-            if self.cpu.pc == 0x388 {
-                let _a = 0;
-            }
-            
-            cycles_to_run += self.cpu.cycle();
-            self.cpu.bus.cycle(cycles_to_run as u8);
-            
-            self.output_temp();
-            cycles += cycles_to_run;
+        self.end_frame();
+    }
+
+    // Ejecuta una instruccion, o el salto a una interrupcion, y devuelve los ciclos que ha tardado
+    pub fn step(&mut self) -> u64 {
+        let mut cycles_to_run = self.cpu.interrupt();
+
+        if self.cpu.pc == 0x388 {
+            let _a = 0;
+        }
+        
+        cycles_to_run += self.cpu.cycle();
+        self.cpu.bus.cycle(cycles_to_run as u8);
+        
+        self.rumble_frame |= self.cpu.bus.rumble();
+        cycles_to_run
+    }
+
+    fn end_frame(&mut self) {
+        self.update_save();
+        self.update_audio();
+        self.update_rumble();
+
+        if let Some(e) = self.cpu.bus.take_serial_error() {
+            self.push_event(GameBoyEvent::SerialDeviceFailed(e.to_string()));
+        }
+    }
+
+    // Muestras de audio estereo intercaladas (izquierda, derecha) a audio_sample_rate()
+    pub fn take_audio_samples(&mut self) -> Vec<f32> {
+        self.cpu.bus.apu.take_samples()
+    }
+
+    pub fn audio_sample_rate(&self) -> u32 {
+        self.cpu.bus.apu.sample_rate()
+    }
+
+    // Se limita a 1 - CPU_FREQUENCY Hz
+    pub fn set_audio_sample_rate(&mut self, sample_rate: u32) {
+        self.cpu.bus.apu.set_sample_rate(sample_rate);
+    }
+
+    // Guardar tambien el audio de cada canal por separado, a la misma frecuencia
+    pub fn set_audio_stems(&mut self, enabled: bool) {
+        self.cpu.bus.apu.set_stem_capture(enabled);
+    }
+
+    // Muestras de los canales 1 - 4 desde la ultima llamada, None si no se estan guardando
+    pub fn take_audio_stems(&mut self) -> Option<[Vec<f32>; 4]> {
+        self.cpu.bus.apu.take_stem_samples()
+    }
+
+    // Envia el audio de cada frame al sink. Mientras haya uno, take_audio_samples no devuelve nada.
+    // La frecuencia del sink tiene que estar entre MIN_SAMPLE_RATE y MAX_SAMPLE_RATE
+    pub fn set_audio_sink(&mut self, sink: Box<dyn AudioSink>) -> Result<(), UnsupportedSampleRate> {
+        let sample_rate = sink.sample_rate();
+        if !(audio::MIN_SAMPLE_RATE..=audio::MAX_SAMPLE_RATE).contains(&sample_rate) {
+            return Err(UnsupportedSampleRate(sample_rate));
+        }
+
+        self.cpu.bus.apu.set_sample_rate(apu::NATIVE_SAMPLE_RATE);
+
+        self.audio = Some(AudioOutput {
+            resampler: Resampler::new(apu::NATIVE_SAMPLE_RATE, sample_rate),
+            sink,
+            buffer: Vec::new(),
+        });
+
+        Ok(())
+    }
+
+    pub fn take_audio_sink(&mut self) -> Option<Box<dyn AudioSink>> {
+        self.audio.take().map(|audio| audio.sink)
+    }
+
+    fn update_audio(&mut self) {
+        let Some(audio) = self.audio.as_mut() else {
+            return;
         };
+
+        let samples = self.cpu.bus.apu.take_samples();
+        audio.buffer.clear();
+        audio.resampler.process(&samples, audio.sink.rate_adjustment(), &mut audio.buffer);
+        audio.sink.push_samples(&audio.buffer);
+    }
+
+    pub fn poll_event(&mut self) -> Option<GameBoyEvent> {
+        self.events.pop_front()
     }
 
-    fn output_temp(&mut self) {
-        if self.cpu.bus.read(0xff02 as usize) == 0x81 {
-            let c = self.cpu.bus.read(0xff01 as usize);
-            print!("{}", char::from(c));
-            self.cpu.bus.write(0xff02 as usize, 0);
+    fn push_event(&mut self, event: GameBoyEvent) {
+        if self.events.len() >= MAX_EVENTS {
+            self.events.pop_front();
         }
+        self.events.push_back(event);
     }
 
-    pub fn draw(&self, window: &mut RenderWindow) {
-        let image = self.cpu.bus.ppu.get_image();
-        let texture = Texture::from_image(&image).unwrap();
-        let mut sprite = Sprite::with_texture(&texture);
-        sprite.set_scale((2.0, 2.0));
-        window.draw(&sprite);
+    // Los juegos encienden y apagan el motor muchas veces por frame para regular la fuerza,
+    // asi que solo se envia un evento por frame: encendido si lo ha estado en algun momento
+    fn update_rumble(&mut self) {
+        let rumble = self.rumble_frame;
+        self.rumble_frame = self.cpu.bus.rumble();
+
+        if rumble != self.rumble {
+            self.rumble = rumble;
+            self.push_event(GameBoyEvent::Rumble(rumble));
+        }
+    }
+
+    // Framebuffer RGBA de SCREEN_WIDTH x SCREEN_HEIGHT, fila a fila
+    pub fn frame_buffer(&self) -> &[u8] {
+        self.cpu.bus.ppu.frame_buffer()
     }
 
     pub fn set_input(&mut self, key: Keys, pressed: bool) {
-        let prev_joyp = self.cpu.bus.read(0xFF00);
-        let val = match key {
-            Keys::Down | Keys::Start => {
-                if pressed {
-                    prev_joyp & 0b11110111
-                } else {
-                    prev_joyp | 0b00001000
-                }
-            },
-            Keys::Up | Keys::Select => {
-                if pressed {
-                    prev_joyp & 0b11111011
-                } else {
-                    prev_joyp | 0b00000100
-                }
-            },
-            Keys::Left | Keys::B => {
-                if pressed {
-                    prev_joyp & 0b11111101
-                } else {
-                    prev_joyp | 0b00000010
-                }
-            },
-            Keys::Right | Keys::A => {
-                if pressed {
-                    prev_joyp & 0b11111110
-                } else {
-                    prev_joyp | 0b00000001
-                }
-            },
-        };
-        self.cpu.bus.set_joyp(val);
-        self.cpu.bus.set_int(Interrupts::Joypad);
+        self.cpu.bus.set_key(key, pressed);
     }
 
     pub fn debug_vram(&self) {
-        let mut step = 0;
         let mut low = 0;
         let mut high;
         let mut tiles = 0;
-        for i in self.cpu.bus.ppu.vram {
+        for (step, i) in self.cpu.bus.ppu.vram.into_iter().enumerate() {
             if step > 0x17FF {
                 println!("{}", tiles / 8);
                 return;
@@ -155,8 +347,6 @@ impl GameBoy {
             if step % 16 == 0 {
                 println!("{:04X}", 0x8000 + step);
             }
-
-            step += 1;
         }
     }
 
@@ -181,3 +371,11 @@ impl GameBoy {
         }
     }
 }
+
+impl Drop for GameBoy {
+    fn drop(&mut self) {
+        if let Err(e) = self.save_ram() {
+            eprintln!("No se ha podido guardar la partida: {}", e);
+        }
+    }
+}
@@ -1,6 +1,7 @@
 use std::collections::VecDeque;
 
-use sfml::graphics::Image;
+pub const SCREEN_WIDTH: usize = 160;
+pub const SCREEN_HEIGHT: usize = 144;
 
 #[derive(Clone, Copy, Debug, PartialEq, Eq)]
 enum TilePixelValue {
@@ -10,6 +11,47 @@ enum TilePixelValue {
     Three
 }
 
+impl TilePixelValue {
+    fn from_bits(low: bool, high: bool) -> Self {
+        match (low, high) {
+            (true, true) => TilePixelValue::Three,
+            (false, true) => TilePixelValue::Two,
+            (true, false) => TilePixelValue::One,
+            (false, false) => TilePixelValue::Zero,
+        }
+    }
+
+    fn index(self) -> usize {
+        match self {
+            TilePixelValue::Zero => 0,
+            TilePixelValue::One => 1,
+            TilePixelValue::Two => 2,
+            TilePixelValue::Three => 3,
+        }
+    }
+}
+
+// Objeto de la OAM seleccionado para la linea actual
+#[derive(Clone, Copy, Debug)]
+struct Sprite {
+    x: u8,
+    tile: u8,
+    flags: u8,
+    // Linea del objeto que toca dibujar, calculada durante el OAM scan
+    row: u8,
+    fetched: bool,
+}
+
+#[derive(Clone, Copy, Debug)]
+struct SpritePixel {
+    value: TilePixelValue,
+    palette: usize,
+    bg_priority: bool,
+}
+
+// Maximo de objetos por linea
+const MAX_SPRITES_LINE: usize = 10;
+
 #[derive(Debug, PartialEq, Eq)]
 enum PpuMode {
     HBlank,
@@ -34,11 +76,12 @@ const LY: usize = 4;
 const LYC: usize = 5;
 // const DMA: usize = 6;
 const BGP: usize = 7;
-// const OBP0: usize = 8;
-// const OBP1: usize = 9;
-// const WY: usize = 10;
-// const WX: usize = 11;
+const OBP0: usize = 8;
+const OBP1: usize = 9;
+const WY: usize = 10;
+const WX: usize = 11;
 
+#[allow(clippy::upper_case_acronyms)]
 pub struct PPU {
     pub vram: [u8; 0x2000],
     pub oam: [u8; 0xA0],
@@ -46,12 +89,19 @@ pub struct PPU {
 
     mode: PpuMode,
     cycles: u64,
+    // Linea de interrupcion de STAT, solo se pide la interrupcion en el flanco de subida
+    stat_line: bool,
+    // El primer frame despues de encender la pantalla no se muestra
+    blank_frame: bool,
 
     scanline_counter: usize,
 
-    fetcher_tilemap: usize,
     fetcher_x: usize,
     fetcher_state: FetcherState,
+    // Cada paso del fetcher tarda 2 ciclos
+    fetcher_ticks: u8,
+    // La primera lectura de cada linea se descarta
+    fetcher_first_fetch: bool,
     fetcher_tile: usize,
     //fetcher_tile: [TilePixelValue; 8],
 
@@ -60,38 +110,64 @@ pub struct PPU {
 
     //pub regs: PpuRegisters,
 
+    // Ventana
+    window_line: usize,
+    window_wy_triggered: bool,
+    window_drawn_line: bool,
+    fetching_window: bool,
+    discard_pixels: usize,
+
+    // Ciclos que quedan de la lectura de un objeto, con la salida de pixeles parada
+    sprite_fetch_dots: usize,
+
     background_fifo: VecDeque<TilePixelValue>,
+    sprite_fifo: VecDeque<SpritePixel>,
+    line_sprites: Vec<Sprite>,
     lcd_x: usize,
-    pub lcd_pixels: [u8; 144 * 160 * 4],
+    pub lcd_pixels: [u8; SCREEN_HEIGHT * SCREEN_WIDTH * 4],
     colors: [Color; 4],
     palette: [Color; 4],
+    obj_palettes: [[Color; 4]; 2],
 }
 
 
 
 impl PPU {
     pub fn new() -> Self {
-        PPU {
+        let mut ppu = PPU {
             vram: [0x00; 0x2000],
             oam: [0x00; 0xA0],
             regs: [0x00; 12],
 
             mode: PpuMode::OamScaning,
             cycles: 0,
+            stat_line: false,
+            blank_frame: false,
 
             scanline_counter: 0,
 
-            fetcher_tilemap: 0x9800,
             fetcher_x: 0,
             fetcher_state: FetcherState::GetTile,
+            fetcher_ticks: 0,
+            fetcher_first_fetch: true,
             fetcher_tile: 0,
 
             data_low: 0,
             data_high: 0,
 
+            window_line: 0,
+            window_wy_triggered: false,
+            window_drawn_line: false,
+            fetching_window: false,
+            discard_pixels: 0,
+
+            sprite_fetch_dots: 0,
+
             background_fifo: VecDeque::with_capacity(16),
+            sprite_fifo: VecDeque::with_capacity(8),
+            line_sprites: Vec::with_capacity(MAX_SPRITES_LINE),
             lcd_x: 0,
-            lcd_pixels: [0x00; 144 * 160 * 4],
+            lcd_pixels: [0x00; SCREEN_HEIGHT * SCREEN_WIDTH * 4],
             colors: [
                 Color{r: 0xE0, g: 0xF8, b: 0xD0},
                 Color{r: 0x88, g: 0xC0, b: 0x70},
@@ -103,8 +179,13 @@ impl PPU {
                 Color{r: 0x88, g: 0xC0, b: 0x70},
                 Color{r: 0x34, g: 0x68, b: 0x56},
                 Color{r: 0x08, g: 0x18, b: 0x20},
-            ]
-        }
+            ],
+            obj_palettes: [[Color{r: 0xE0, g: 0xF8, b: 0xD0}; 4]; 2],
+        };
+
+        // Hasta que se dibuje el primer frame la pantalla esta en blanco
+        ppu.clear_screen();
+        ppu
     }
 
     fn read_vram_ppu(&self, dir: usize) -> u8 {
@@ -134,8 +215,85 @@ impl PPU {
         self.oam[dir - 0xFE00] = val;
     }
 
-    pub fn get_image(&self) -> Image {
-        Image::create_from_pixels(160, 144, &self.lcd_pixels).unwrap()
+    pub fn read_reg(&self, dir: usize) -> u8 {
+        match dir - 0xFF40 {
+            // El bit 7 de STAT no existe y se lee a 1
+            STAT => self.regs[STAT] | 0b10000000,
+            reg => self.regs[reg],
+        }
+    }
+
+    pub fn write_reg(&mut self, dir: usize, val: u8) {
+        match dir - 0xFF40 {
+            LCDC => {
+                let was_enabled = self.lcd_enabled();
+                self.regs[LCDC] = val;
+
+                if was_enabled && !self.lcd_enabled() {
+                    self.lcd_off();
+                } else if !was_enabled && self.lcd_enabled() {
+                    self.lcd_on();
+                }
+            },
+            // Solo se pueden escribir las fuentes de interrupcion, el modo y la coincidencia con LYC son de lectura
+            STAT => self.regs[STAT] = (val & 0b01111000) | (self.regs[STAT] & 0b00000111),
+            LY => {},
+            reg => self.regs[reg] = val,
+        }
+    }
+
+    fn lcd_enabled(&self) -> bool {
+        self.regs[LCDC] & 0b10000000 != 0
+    }
+
+    // Con la pantalla apagada LY se queda a 0, STAT en modo 0 y la VRAM y la OAM son accesibles
+    fn lcd_off(&mut self) {
+        self.mode = PpuMode::HBlank;
+        self.cycles = 0;
+        self.scanline_counter = 0;
+        self.regs[LY] = 0;
+        self.regs[STAT] &= 0b11111100;
+        self.stat_line = false;
+
+        self.reset_line();
+        self.window_line = 0;
+        self.window_wy_triggered = false;
+
+        self.clear_screen();
+    }
+
+    fn clear_screen(&mut self) {
+        let white = self.colors[0];
+        for pixel in self.lcd_pixels.chunks_exact_mut(4) {
+            pixel.copy_from_slice(&[white.r, white.g, white.b, 0xFF]);
+        }
+    }
+
+    // Al encender se empieza desde el principio del frame
+    fn lcd_on(&mut self) {
+        self.mode = PpuMode::OamScaning;
+        self.cycles = 0;
+        self.scanline_counter = 0;
+        self.regs[LY] = 0;
+        self.blank_frame = true;
+    }
+
+    // Estado del fetcher y de la salida de pixeles al acabar cada linea
+    fn reset_line(&mut self) {
+        self.fetcher_x = 0;
+        self.lcd_x = 0;
+        self.background_fifo.clear();
+        self.sprite_fifo.clear();
+        self.fetcher_state = FetcherState::GetTile;
+        self.fetcher_ticks = 0;
+        self.discard_pixels = 0;
+        self.sprite_fetch_dots = 0;
+        self.window_drawn_line = false;
+        self.fetching_window = false;
+    }
+
+    pub fn frame_buffer(&self) -> &[u8] {
+        &self.lcd_pixels
     }
 
     // (VBlank, STAT)
@@ -144,129 +302,185 @@ impl PPU {
 
         let mut int = (false, false);
 
-        if self.regs[LCDC] & 0b10000000 == 0 {
+        if !self.lcd_enabled() {
             return int;
         }
 
-        self.palette = [
-            self.colors[0b00000011 & self.regs[BGP] as usize],
-            self.colors[(0b00001100 & self.regs[BGP] as usize) >> 2],
-            self.colors[(0b00110000 & self.regs[BGP] as usize) >> 4],
-            self.colors[(0b11000000 & self.regs[BGP] as usize) >> 6],
+        self.palette = self.decode_palette(self.regs[BGP]);
+        self.obj_palettes = [
+            self.decode_palette(self.regs[OBP0]),
+            self.decode_palette(self.regs[OBP1]),
         ];
 
         while cycles_to_tick > 0 {
-            self.regs[STAT] &= 0b10000111;
-
             match self.mode {
                 PpuMode::HBlank => {
-
-                    self.regs[STAT] = self.regs[STAT] & 0b11111100 + 0b00;
-
                     if self.scanline_counter == 455 {
                         if self.regs[LY] == 143 {
                             self.mode = PpuMode::VBlank;
+                            self.blank_frame = false;
                             int.0 = true;
-                            self.regs[STAT] |= 0b00010000;
                         } else {
                             self.mode = PpuMode::OamScaning;
-                            self.regs[STAT] |= 0b00100000;
                         }
                     }
                 },
                 PpuMode::OamScaning => {
+                    self.oam_scan_cycle();
 
-                    self.regs[STAT] = self.regs[STAT] & 0b11111100 + 0b10;
+                    // La ventana solo puede empezar a dibujarse una vez que LY ha coincidido con WY
+                    if self.regs[LY] == self.regs[WY] {
+                        self.window_wy_triggered = true;
+                    }
 
                     if self.scanline_counter == 79 {
                         self.mode = PpuMode::Drawing;
+                        // El scroll fino se hace descartando los primeros SCX % 8 pixeles
+                        self.discard_pixels = (self.regs[SCX] & 0b111) as usize;
+                        self.fetcher_first_fetch = true;
                     }
                 },
                 PpuMode::Drawing => {
-                    self.fetcher_cycle(&cycles_to_tick);
-                    self.pixel_mixer_cycle();
-
-                    self.regs[STAT] = self.regs[STAT] & 0b11111100 + 0b11;
+                    self.drawing_cycle();
 
                     if self.lcd_x == 160 {
                         self.mode = PpuMode::HBlank;
-                        self.regs[STAT] |= 0b00001000;
-                        self.fetcher_x = 0;
-                        self.lcd_x = 0;
-                        self.background_fifo.clear();
-                        self.fetcher_state = FetcherState::GetTile;
+
+                        // El contador interno de la ventana solo avanza si se ha dibujado en esta linea
+                        if self.window_drawn_line {
+                            self.window_line += 1;
+                        }
+                        self.reset_line();
                     }
                 },
                 PpuMode::VBlank => {
-
-                    self.regs[STAT] = self.regs[STAT] & 0b11111100 + 0b01;
-
-                    if self.scanline_counter == 455 && self.regs[LY] == 153 {
+                    if self.scanline_counter == 455 && self.line() == 153 {
                         self.mode = PpuMode::OamScaning;
+                        self.window_line = 0;
+                        self.window_wy_triggered = false;
                     }
                 }
             }
 
-            if self.regs[LY] == self.regs[LYC] {
-                self.regs[STAT] |= 0b00000100;
-                self.regs[STAT] |= 0b01000000;
+            self.cycles += 1;
+            self.scanline_counter = (self.cycles % 456) as usize;
+            // En la linea 153 LY vale 0 a partir del ciclo 4
+            self.regs[LY] = if self.line() == 153 && self.scanline_counter >= 4 {
+                0
             } else {
-                self.regs[STAT] &= 0b11111011;
-            }
+                self.line() as u8
+            };
 
-            if self.regs[STAT] & 0b01111100 != 0 {
+            if self.update_stat() {
                 int.1 = true;
             }
 
-            self.cycles += 1;
-            self.regs[LY] = ((self.cycles / 456) % 154) as u8;
-            self.scanline_counter = (self.cycles % 456) as usize;
             cycles_to_tick -= 1;
         }
 
-        return int;
+        int
     }
 
+    // Linea real (0 - 153), LY no coincide al final de la 153
+    fn line(&self) -> usize {
+        ((self.cycles / 456) % 154) as usize
+    }
+
+    // Actualiza el modo y la coincidencia con LYC en STAT. Devuelve true en el flanco de subida
+    // de la linea de interrupcion, que es el OR de todas las fuentes activadas
+    fn update_stat(&mut self) -> bool {
+        let mode = match self.mode {
+            PpuMode::HBlank => 0,
+            PpuMode::VBlank => 1,
+            PpuMode::OamScaning => 2,
+            PpuMode::Drawing => 3,
+        };
+        let coincidence = self.regs[LY] == self.regs[LYC];
+
+        let stat = (self.regs[STAT] & 0b01111000) | ((coincidence as u8) << 2) | mode;
+        self.regs[STAT] = stat;
+
+        let line = (mode == 0 && stat & 0b00001000 != 0)
+            || (mode == 1 && stat & 0b00010000 != 0)
+            || (mode == 2 && stat & 0b00100000 != 0)
+            // Al entrar en VBlank tambien salta la fuente del modo 2
+            || (mode == 1 && self.line() == 144 && self.scanline_counter == 0 && stat & 0b00100000 != 0)
+            || (coincidence && stat & 0b01000000 != 0);
+
+        let rising_edge = line && !self.stat_line;
+        self.stat_line = line;
+
+        rising_edge
+    }
 
 
-    fn fetcher_cycle(&mut self, cycles: &u8) {
-        if cycles % 2 != 0 {
+
+    // Un ciclo del modo 3. La duracion del modo depende del scroll, la ventana y los objetos,
+    // y el HBlank dura lo que falte hasta los 456 ciclos de la linea
+    fn drawing_cycle(&mut self) {
+        // Mientras se lee un objeto el fetcher del fondo y la salida de pixeles estan parados
+        if self.sprite_fetch_dots > 0 {
+            self.sprite_fetch_dots -= 1;
+            if self.sprite_fetch_dots == 0 {
+                if let Some(i) = self.pending_sprite() {
+                    self.sprite_fetch(i);
+                }
+            }
             return;
         }
 
-        match self.fetcher_state {
-            FetcherState::GetTile => {
-                if self.regs[LCDC] & 0b00001000 != 0 && false /*TODO Saber si no está en pantalla, false temporal para que no entre */ {
-                    self.fetcher_tilemap = 0x9C00;
-                }
-                if self.regs[LCDC] & 0b01000000 != 0 && false /*TODO Saber si está en pantalla, false temporal para que no entre */ {
-                    self.fetcher_tilemap = 0x9C00;
-                }
+        self.window_check();
+        self.fetcher_cycle();
 
-                let x = if false /*TODO WINDOW */ {
-                    0
-                } else {
-                    ((self.regs[SCX] as usize / 8) + self.fetcher_x) & 0x1F
-                };
+        if self.discard_pixels == 0 && !self.background_fifo.is_empty() && self.pending_sprite().is_some() {
+            // La lectura del objeto empieza cuando el fetcher del fondo termina el tile actual
+            let fetcher_idle = self.fetcher_state == FetcherState::PushToFIFO
+                || (self.fetcher_state == FetcherState::GetTile && self.fetcher_ticks == 0);
+
+            if fetcher_idle {
+                self.sprite_fetch_dots = 6;
+            }
+            return;
+        }
 
-                let y = if false /*TODO WINDOW */ {
-                    0
+        self.pixel_mixer_cycle();
+    }
+
+    fn fetcher_cycle(&mut self) {
+        // GetTile, GetDataLow y GetDataHigh tardan 2 ciclos, PushToFIFO espera a que la FIFO este vacia
+        if self.fetcher_state != FetcherState::PushToFIFO {
+            self.fetcher_ticks += 1;
+            if self.fetcher_ticks < 2 {
+                return;
+            }
+            self.fetcher_ticks = 0;
+        }
+
+        match self.fetcher_state {
+            FetcherState::GetTile => {
+                let (x, y, tilemap) = if self.fetching_window {
+                    let tilemap = if self.regs[LCDC] & 0b01000000 != 0 { 0x9C00 } else { 0x9800 };
+                    (self.fetcher_x & 0x1F, 32 * (self.window_line / 8), tilemap)
                 } else {
-                    32 * (((self.regs[LY] as usize + self.regs[SCY] as usize) & 255) / 8)
+                    (
+                        ((self.regs[SCX] as usize / 8) + self.fetcher_x) & 0x1F,
+                        32 * (((self.regs[LY] as usize + self.regs[SCY] as usize) & 255) / 8),
+                        if self.regs[LCDC] & 0b00001000 != 0 { 0x9C00 } else { 0x9800 },
+                    )
                 };
 
-                self.fetcher_tile = self.read_vram_ppu(x + y + self.fetcher_tilemap) as usize;
+                self.fetcher_tile = self.read_vram_ppu(x + y + tilemap) as usize;
                 
                 
                 self.fetcher_state = FetcherState::GetDataLow;
             },
             FetcherState::GetDataLow => {
                 let dir = if self.regs[LCDC] & 0b00010000 != 0 {
-                    0x8000 + (self.fetcher_tile * 0x10) + 2 * ((self.regs[LY] as usize + self.regs[SCY] as usize) % 8) + 1
+                    0x8000 + (self.fetcher_tile * 0x10) + 2 * self.fetcher_tile_line() + 1
                 } else {
                     let signed_tile = self.fetcher_tile as i8 as i32;
-                    let x_offset = 0x9000 + signed_tile * 0x10 as i32;
-                    x_offset as usize + 2 * ((self.regs[LY] as usize + self.regs[SCY] as usize) % 8) + 1
+                    let x_offset = 0x9000 + signed_tile * 0x10;
+                    x_offset as usize + 2 * self.fetcher_tile_line() + 1
                 };
 
                 self.data_low = self.read_vram_ppu(dir);
@@ -275,23 +489,24 @@ impl PPU {
             },
             FetcherState::GetDataHigh => {
                 let dir = if self.regs[LCDC] & 0b00010000 != 0 {
-                    0x8000 + (self.fetcher_tile * 0x10) + 2 * ((self.regs[LY] as usize + self.regs[SCY] as usize) % 8)
+                    0x8000 + (self.fetcher_tile * 0x10) + 2 * self.fetcher_tile_line()
                 } else {
                     let signed_tile = self.fetcher_tile as i8 as i32;
-                    let x_offset = 0x9000 + signed_tile * 0x10 as i32;
-                    x_offset as usize + 2 * ((self.regs[LY] as usize + self.regs[SCY] as usize) % 8)
+                    let x_offset = 0x9000 + signed_tile * 0x10;
+                    x_offset as usize + 2 * self.fetcher_tile_line()
                 };
 
                 self.data_high = self.read_vram_ppu(dir);
 
-                if self.fetcher_tile == 0x18 {
-                    let _a = 1;
+                if self.fetcher_first_fetch {
+                    self.fetcher_first_fetch = false;
+                    self.fetcher_state = FetcherState::GetTile;
+                } else {
+                    self.fetcher_state = FetcherState::PushToFIFO;
                 }
-
-                self.fetcher_state = FetcherState::PushToFIFO;
             },
             FetcherState::PushToFIFO => {
-                if self.background_fifo.len() > 8 {
+                if !self.background_fifo.is_empty() {
                     return;
                 }
                 for i in 0..8 {
@@ -315,45 +530,182 @@ impl PPU {
         }
     }
 
+    // Linea dentro del tile que esta leyendo el fetcher
+    fn fetcher_tile_line(&self) -> usize {
+        if self.fetching_window {
+            self.window_line % 8
+        } else {
+            (self.regs[LY] as usize + self.regs[SCY] as usize) % 8
+        }
+    }
+
+    // Reinicia el fetcher si la ventana empieza en la posicion actual
+    fn window_check(&mut self) {
+        // En DMG el bit 0 de LCDC tambien desactiva la ventana
+        if self.fetching_window || !self.window_wy_triggered || self.regs[LCDC] & 0b00100001 != 0b00100001 {
+            return;
+        }
+
+        let wx = self.regs[WX] as usize;
+        if wx > 166 || self.lcd_x + 7 < wx {
+            return;
+        }
+
+        self.fetching_window = true;
+        self.window_drawn_line = true;
+        self.background_fifo.clear();
+        self.fetcher_x = 0;
+        self.fetcher_state = FetcherState::GetTile;
+        self.fetcher_ticks = 0;
+
+        // Con WX < 7 la ventana empieza fuera de la pantalla y se descartan sus primeros pixeles
+        self.discard_pixels = 7usize.saturating_sub(wx);
+    }
+
     fn pixel_mixer_cycle(&mut self) {
         if self.background_fifo.is_empty() {
             return;
         }
 
+        if self.discard_pixels > 0 {
+            self.background_fifo.pop_front();
+            self.discard_pixels -= 1;
+            return;
+        }
+
         let bg_pixel = self.background_fifo.pop_front().unwrap();
+        let sprite_pixel = self.sprite_fifo.pop_front().filter(|_| self.regs[LCDC] & 0b00000010 != 0);
+
+        // Con el bit 0 de LCDC a 0 el fondo se ve blanco y los objetos siempre por encima
+        let bg_enabled = self.regs[LCDC] & 0b00000001 != 0;
+        let bg_pixel = if bg_enabled { bg_pixel } else { TilePixelValue::Zero };
+        let bg_color = if bg_enabled { self.palette[bg_pixel.index()] } else { self.colors[0] };
 
-        let pos = (self.lcd_x + self.regs[LY] as usize * 160) * 4;
+        let pos = (self.lcd_x + self.regs[LY] as usize * SCREEN_WIDTH) * 4;
+        self.lcd_x += 1;
 
-        // TODO Sprite FIFO
+        if self.blank_frame {
+            return;
+        }
 
-        match bg_pixel {
-            TilePixelValue::Zero => {
-                self.lcd_pixels[pos] = self.palette[0].r;
-                self.lcd_pixels[pos + 1] = self.palette[0].g;
-                self.lcd_pixels[pos + 2] = self.palette[0].b;
-                self.lcd_pixels[pos + 3] = 0xFF;
+        let color = match sprite_pixel {
+            // El color 0 de los objetos es transparente, y con la prioridad activada
+            // el objeto solo se ve sobre el color 0 del fondo
+            Some(sprite) if sprite.value != TilePixelValue::Zero
+                && !(sprite.bg_priority && bg_pixel != TilePixelValue::Zero) => {
+                self.obj_palettes[sprite.palette][sprite.value.index()]
             },
-            TilePixelValue::One => {
-                self.lcd_pixels[pos] = self.palette[1].r;
-                self.lcd_pixels[pos + 1] = self.palette[1].g;
-                self.lcd_pixels[pos + 2] = self.palette[1].b;
-                self.lcd_pixels[pos + 3] = 0xFF;
-            }
-            TilePixelValue::Two => {
-                self.lcd_pixels[pos] = self.palette[2].r;
-                self.lcd_pixels[pos + 1] = self.palette[2].g;
-                self.lcd_pixels[pos + 2] = self.palette[2].b;
-                self.lcd_pixels[pos + 3] = 0xFF;
+            _ => bg_color,
+        };
+
+        self.lcd_pixels[pos] = color.r;
+        self.lcd_pixels[pos + 1] = color.g;
+        self.lcd_pixels[pos + 2] = color.b;
+        self.lcd_pixels[pos + 3] = 0xFF;
+    }
+
+    // Se comprueba una entrada de la OAM cada 2 ciclos
+    fn oam_scan_cycle(&mut self) {
+        if self.scanline_counter == 0 {
+            self.line_sprites.clear();
+        }
+
+        if self.scanline_counter.is_multiple_of(2) && self.line_sprites.len() < MAX_SPRITES_LINE {
+            let entry = (self.scanline_counter / 2) * 4;
+            let y = self.oam[entry];
+            let line = self.regs[LY] as usize + 16;
+
+            if line >= y as usize && line < y as usize + self.sprite_height() {
+                self.line_sprites.push(Sprite {
+                    x: self.oam[entry + 1],
+                    tile: self.oam[entry + 2],
+                    flags: self.oam[entry + 3],
+                    row: (line - y as usize) as u8,
+                    fetched: false,
+                });
             }
-            TilePixelValue::Three => {
-                self.lcd_pixels[pos] = self.palette[3].r;
-                self.lcd_pixels[pos + 1] = self.palette[3].g;
-                self.lcd_pixels[pos + 2] = self.palette[3].b;
-                self.lcd_pixels[pos + 3] = 0xFF;
+        }
+
+        if self.scanline_counter == 79 {
+            // En DMG tiene prioridad el objeto con menor X, y a igual X el de menor indice
+            // en la OAM. sort_by_key es estable, asi que se mantiene el orden de la OAM.
+            self.line_sprites.sort_by_key(|sprite| sprite.x);
+        }
+    }
+
+    fn sprite_height(&self) -> usize {
+        if self.regs[LCDC] & 0b00000100 != 0 {
+            16
+        } else {
+            8
+        }
+    }
+
+    // Primer objeto de la linea que empieza en la posicion actual y no se ha leido.
+    // Los objetos con X = 0 tambien se leen aunque no se vean
+    fn pending_sprite(&self) -> Option<usize> {
+        // Con los objetos desactivados no se leen ni paran el fetcher
+        if self.regs[LCDC] & 0b00000010 == 0 {
+            return None;
+        }
+
+        self.line_sprites
+            .iter()
+            .position(|sprite| !sprite.fetched && sprite.x as usize <= self.lcd_x + 8)
+    }
+
+    // Mezcla el objeto en la FIFO de objetos
+    fn sprite_fetch(&mut self, i: usize) {
+        let height = self.sprite_height();
+        let sprite = self.line_sprites[i];
+        self.line_sprites[i].fetched = true;
+
+        // Si LCDC cambia de 8x16 a 8x8 despues del OAM scan la linea puede pasar de 7
+        let mut line = sprite.row as usize & (height - 1);
+        if sprite.flags & 0b01000000 != 0 {
+            line = height - 1 - line;
+        }
+
+        let tile = if height == 16 {
+            sprite.tile & 0xFE
+        } else {
+            sprite.tile
+        } as usize;
+
+        let dir = 0x8000 + tile * 0x10 + line * 2;
+        let low = self.read_vram_ppu(dir);
+        let high = self.read_vram_ppu(dir + 1);
+
+        // Pixeles que quedan fuera por la izquierda de la pantalla
+        let discard = self.lcd_x + 8 - sprite.x as usize;
+
+        for (slot, px) in (discard..8).enumerate() {
+            let bit = if sprite.flags & 0b00100000 != 0 { px } else { 7 - px };
+            let mask = 1 << bit;
+
+            let pixel = SpritePixel {
+                value: TilePixelValue::from_bits(low & mask != 0, high & mask != 0),
+                palette: ((sprite.flags & 0b00010000) >> 4) as usize,
+                bg_priority: sprite.flags & 0b10000000 != 0,
+            };
+
+            match self.sprite_fifo.get_mut(slot) {
+                // Solo se sobreescriben los pixeles transparentes
+                Some(old) => if old.value == TilePixelValue::Zero {
+                    *old = pixel;
+                },
+                None => self.sprite_fifo.push_back(pixel),
             }
         }
+    }
 
-        self.lcd_x += 1;
+    fn decode_palette(&self, reg: u8) -> [Color; 4] {
+        [
+            self.colors[0b00000011 & reg as usize],
+            self.colors[(0b00001100 & reg as usize) >> 2],
+            self.colors[(0b00110000 & reg as usize) >> 4],
+            self.colors[(0b11000000 & reg as usize) >> 6],
+        ]
     }
 }
 
@@ -0,0 +1,450 @@
+use std::{fs::File, io::{self, BufWriter}, path::{Path, PathBuf}};
+
+use super::{apu::CPU_FREQUENCY, serial::SerialDevice};
+
+const MAGIC: [u8; 2] = [0x88, 0x33];
+
+const INIT: u8 = 0x01;
+const PRINT: u8 = 0x02;
+const DATA: u8 = 0x04;
+const STATUS: u8 = 0x0F;
+
+// Bits del byte de estado
+const STATUS_CHECKSUM_ERROR: u8 = 0b00000001;
+const STATUS_PRINTING: u8 = 0b00000010;
+const STATUS_READY: u8 = 0b00000100;
+const STATUS_UNPROCESSED: u8 = 0b00001000;
+
+// Respuesta al primer byte despues del checksum, indica que hay una impresora conectada
+const ALIVE: u8 = 0x81;
+
+pub const PRINTER_WIDTH: usize = 160;
+const TILES_PER_ROW: usize = PRINTER_WIDTH / 8;
+const BYTES_PER_TILE_ROW: usize = TILES_PER_ROW * 16;
+// La memoria de la impresora tiene para 9 paquetes de 2 filas de tiles, 144 lineas
+const IMAGE_SIZE: usize = 9 * 2 * BYTES_PER_TILE_ROW;
+
+// Tiempo que tarda en imprimir cada linea
+const CYCLES_PER_LINE: u32 = CPU_FREQUENCY / 64;
+
+// Tono de cada color con la exposicion por defecto (0x40)
+const SHADES: [f32; 4] = [0.0, 1.0 / 3.0, 2.0 / 3.0, 1.0];
+const DEFAULT_EXPOSURE: u8 = 0x40;
+
+// Parte del paquete que se espera recibir
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Stage {
+    Magic(usize),
+    Command,
+    Compression,
+    LengthLow,
+    LengthHigh,
+    Data,
+    ChecksumLow,
+    ChecksumHigh,
+    Alive,
+    Status,
+}
+
+struct Packet {
+    command: u8,
+    compressed: bool,
+    length: u16,
+    data: Vec<u8>,
+    checksum: u16,
+    // Suma de los bytes desde el comando hasta el final de los datos
+    sum: u16,
+}
+
+impl Packet {
+    fn new() -> Self {
+        Packet {
+            command: 0,
+            compressed: false,
+            length: 0,
+            data: Vec::new(),
+            checksum: 0,
+            sum: 0,
+        }
+    }
+}
+
+// Game Boy Printer. Cada tira de papel impresa se guarda como PNG en escala de grises en el directorio de salida.
+// Una tira termina cuando se imprime con margen inferior o empieza otra con margen superior
+pub struct Printer {
+    out_dir: PathBuf,
+
+    stage: Stage,
+    packet: Packet,
+    status: u8,
+    // Ciclos hasta que termina de imprimir
+    printing_cycles: u32,
+
+    // Datos de tiles recibidos, sin comprimir
+    image: Vec<u8>,
+    // Lineas de la tira actual, un byte por pixel
+    strip: Vec<u8>,
+    // Error al guardar una tira durante la emulacion, ver take_error
+    error: Option<io::Error>,
+}
+
+impl Printer {
+    // El directorio se crea si no existe
+    pub fn new<P: AsRef<Path>>(out_dir: P) -> io::Result<Self> {
+        std::fs::create_dir_all(&out_dir)?;
+
+        Ok(Printer {
+            out_dir: out_dir.as_ref().to_path_buf(),
+
+            stage: Stage::Magic(0),
+            packet: Packet::new(),
+            status: 0x00,
+            printing_cycles: 0,
+
+            image: Vec::with_capacity(IMAGE_SIZE),
+            strip: Vec::new(),
+            error: None,
+        })
+    }
+
+    // Recibe un byte y devuelve el que envia la impresora a la vez
+    fn receive(&mut self, val: u8) -> u8 {
+        let mut reply = 0x00;
+
+        self.stage = match self.stage {
+            Stage::Magic(i) if val == MAGIC[i] => {
+                if i + 1 < MAGIC.len() {
+                    Stage::Magic(i + 1)
+                } else {
+                    self.packet = Packet::new();
+                    Stage::Command
+                }
+            },
+            // Si no llega la secuencia de inicio se vuelve a esperar desde el principio
+            Stage::Magic(_) => Stage::Magic(if val == MAGIC[0] { 1 } else { 0 }),
+            Stage::Command => {
+                self.packet.command = val;
+                self.packet.sum = val as u16;
+                Stage::Compression
+            },
+            Stage::Compression => {
+                self.packet.compressed = val & 0x01 != 0;
+                self.packet.sum = self.packet.sum.wrapping_add(val as u16);
+                Stage::LengthLow
+            },
+            Stage::LengthLow => {
+                self.packet.length = val as u16;
+                self.packet.sum = self.packet.sum.wrapping_add(val as u16);
+                Stage::LengthHigh
+            },
+            Stage::LengthHigh => {
+                self.packet.length |= (val as u16) << 8;
+                self.packet.sum = self.packet.sum.wrapping_add(val as u16);
+                if self.packet.length == 0 { Stage::ChecksumLow } else { Stage::Data }
+            },
+            Stage::Data => {
+                self.packet.data.push(val);
+                self.packet.sum = self.packet.sum.wrapping_add(val as u16);
+                if self.packet.data.len() < self.packet.length as usize { Stage::Data } else { Stage::ChecksumLow }
+            },
+            Stage::ChecksumLow => {
+                self.packet.checksum = val as u16;
+                Stage::ChecksumHigh
+            },
+            Stage::ChecksumHigh => {
+                self.packet.checksum |= (val as u16) << 8;
+                self.execute();
+                Stage::Alive
+            },
+            Stage::Alive => {
+                reply = ALIVE;
+                Stage::Status
+            },
+            Stage::Status => {
+                reply = self.status;
+                // El aviso de impresion terminada solo se envia una vez
+                if self.status & STATUS_READY != 0 && self.status & STATUS_PRINTING == 0 {
+                    self.status &= !STATUS_READY;
+                }
+                Stage::Magic(0)
+            },
+        };
+
+        reply
+    }
+
+    fn execute(&mut self) {
+        if self.packet.checksum != self.packet.sum {
+            self.status |= STATUS_CHECKSUM_ERROR;
+            return;
+        }
+        self.status &= !STATUS_CHECKSUM_ERROR;
+
+        match self.packet.command {
+            INIT => {
+                self.image.clear();
+                self.status = 0x00;
+                self.printing_cycles = 0;
+            },
+            PRINT if self.status & STATUS_PRINTING == 0 && self.packet.data.len() >= 4 => {
+                let (sheets, margins, palette, exposure) = (self.packet.data[0], self.packet.data[1], self.packet.data[2], self.packet.data[3]);
+                self.print(sheets, margins, palette, exposure);
+            },
+            DATA => {
+                let data = std::mem::take(&mut self.packet.data);
+                if self.packet.compressed {
+                    decompress(&data, &mut self.image);
+                } else {
+                    self.image.extend_from_slice(&data);
+                }
+                self.image.truncate(IMAGE_SIZE);
+
+                // Un paquete sin datos indica que la imagen esta completa
+                if data.is_empty() || self.image.len() == IMAGE_SIZE {
+                    self.status |= STATUS_READY;
+                }
+                self.status |= STATUS_UNPROCESSED;
+            },
+            // STATUS solo pide el estado, que se envia al final de todos los paquetes
+            STATUS => {},
+            _ => {},
+        }
+    }
+
+    // Margenes: nibble alto antes de imprimir, nibble bajo despues
+    fn print(&mut self, sheets: u8, margins: u8, palette: u8, exposure: u8) {
+        if margins >> 4 != 0 {
+            self.finish_strip_or_record();
+        }
+
+        // Algunos juegos envian la paleta a 0, que se trata como la normal
+        let palette = if palette == 0 { 0b11100100 } else { palette };
+        let lines = render(&self.image, palette, exposure & 0x7F);
+        for _ in 0..sheets {
+            self.strip.extend_from_slice(&lines);
+        }
+
+        if margins & 0x0F != 0 {
+            self.finish_strip_or_record();
+        }
+
+        self.image.clear();
+        self.status = STATUS_PRINTING | STATUS_READY;
+        self.printing_cycles = ((lines.len() / PRINTER_WIDTH) as u32 * sheets as u32 * CYCLES_PER_LINE).max(1);
+    }
+
+    // Guarda la tira actual, si hay algo impreso
+    pub fn finish_strip(&mut self) -> io::Result<()> {
+        if self.strip.is_empty() {
+            return Ok(());
+        }
+
+        let strip = std::mem::take(&mut self.strip);
+        self.write_png(&strip)
+    }
+
+    // Las tiras que se terminan al imprimir no pueden devolver el error, se guarda el ultimo
+    fn finish_strip_or_record(&mut self) {
+        if let Err(e) = self.finish_strip() {
+            self.error = Some(e);
+        }
+    }
+
+
+    fn write_png(&self, strip: &[u8]) -> io::Result<()> {
+        // Primer nombre libre, para no sobrescribir impresiones anteriores
+        let path = (0..)
+            .map(|i| self.out_dir.join(format!("print_{:04}.png", i)))
+            .find(|path| !path.exists())
+            .unwrap();
+
+        let mut encoder = png::Encoder::new(BufWriter::new(File::create(path)?), PRINTER_WIDTH as u32, (strip.len() / PRINTER_WIDTH) as u32);
+        encoder.set_color(png::ColorType::Grayscale);
+        encoder.set_depth(png::BitDepth::Eight);
+
+        let mut writer = encoder.write_header()?;
+        writer.write_image_data(strip)?;
+        writer.finish()?;
+
+        Ok(())
+    }
+}
+
+impl Drop for Printer {
+    // Solo llega aqui si no se ha llamado a finish, no hay nadie a quien devolver el error
+    fn drop(&mut self) {
+        if let Err(e) = self.finish_strip() {
+            eprintln!("No se ha podido guardar la impresion: {}", e);
+        }
+    }
+}
+
+impl SerialDevice for Printer {
+    fn transfer(&mut self, out: u8) -> u8 {
+        self.receive(out)
+    }
+
+    fn update(&mut self, cycles: u8, _sb: u8, _waiting: bool) -> Option<u8> {
+        if self.printing_cycles > 0 {
+            self.printing_cycles = self.printing_cycles.saturating_sub(cycles as u32);
+            if self.printing_cycles == 0 {
+                self.status &= !(STATUS_PRINTING | STATUS_UNPROCESSED);
+            }
+        }
+
+        None
+    }
+
+    fn take_error(&mut self) -> Option<io::Error> {
+        self.error.take()
+    }
+
+    fn finish(&mut self) -> io::Result<()> {
+        self.finish_strip()
+    }
+}
+
+// RLE: si el bit 7 del byte de control esta a 1 el siguiente byte se repite (n & 0x7F) + 2 veces,
+// si no se copian los n + 1 bytes siguientes
+fn decompress(data: &[u8], out: &mut Vec<u8>) {
+    let mut i = 0;
+
+    while i < data.len() {
+        let control = data[i];
+        i += 1;
+
+        if control & 0x80 != 0 {
+            let Some(&val) = data.get(i) else {
+                break;
+            };
+            out.extend(std::iter::repeat_n(val, (control & 0x7F) as usize + 2));
+            i += 1;
+        } else {
+            let end = (i + control as usize + 1).min(data.len());
+            out.extend_from_slice(&data[i..end]);
+            i = end;
+        }
+    }
+}
+
+// Convierte los tiles recibidos en lineas de PRINTER_WIDTH pixeles, 0 negro y 0xFF blanco
+fn render(image: &[u8], palette: u8, exposure: u8) -> Vec<u8> {
+    let height = image.len() / BYTES_PER_TILE_ROW * 8;
+    let mut lines = vec![0xFF; height * PRINTER_WIDTH];
+
+    // Mas exposicion oscurece la imagen, entre -25% y +25%
+    let darkness = 1.0 + (exposure as f32 - DEFAULT_EXPOSURE as f32) / 0x7F as f32 * 0.5;
+
+    for y in 0..height {
+        for x in 0..PRINTER_WIDTH {
+            let tile = (y / 8) * TILES_PER_ROW + x / 8;
+            let addr = tile * 16 + (y % 8) * 2;
+            let bit = 7 - (x % 8);
+
+            let color = (((image[addr + 1] >> bit) & 0x01) << 1) | ((image[addr] >> bit) & 0x01);
+            let shade = (palette >> (color * 2)) & 0b11;
+
+            let tone = (SHADES[shade as usize] * darkness).clamp(0.0, 1.0);
+            lines[y * PRINTER_WIDTH + x] = ((1.0 - tone) * 255.0).round() as u8;
+        }
+    }
+
+    lines
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn printer(name: &str) -> (Printer, PathBuf) {
+        let dir = std::env::temp_dir().join(format!("rust_gbc_printer_{}_{}", std::process::id(), name));
+        let _ = std::fs::remove_dir_all(&dir);
+        (Printer::new(&dir).unwrap(), dir)
+    }
+
+    // Envia un paquete y devuelve las respuestas a los dos ultimos bytes (alive, estado)
+    fn send(printer: &mut Printer, command: u8, compression: u8, data: &[u8], checksum_error: bool) -> (u8, u8) {
+        let mut bytes = vec![command, compression, data.len() as u8, (data.len() >> 8) as u8];
+        bytes.extend_from_slice(data);
+
+        let mut checksum = bytes.iter().fold(0u16, |sum, &b| sum.wrapping_add(b as u16));
+        if checksum_error {
+            checksum = checksum.wrapping_add(1);
+        }
+
+        for byte in MAGIC.iter().chain(&bytes).chain(&checksum.to_le_bytes()) {
+            assert_eq!(printer.transfer(*byte), 0x00);
+        }
+
+        (printer.transfer(0x00), printer.transfer(0x00))
+    }
+
+    #[test]
+    fn alive_and_status() {
+        let (mut printer, _) = printer("alive");
+
+        assert_eq!(send(&mut printer, INIT, 0, &[], false), (ALIVE, 0x00));
+        assert_eq!(send(&mut printer, STATUS, 0, &[], false), (ALIVE, 0x00));
+
+        // Los bytes fuera de un paquete se ignoran
+        assert_eq!(printer.transfer(0x12), 0x00);
+        assert_eq!(printer.transfer(0x88), 0x00);
+        assert_eq!(send(&mut printer, STATUS, 0, &[], false), (ALIVE, 0x00));
+    }
+
+    #[test]
+    fn checksum_error() {
+        let (mut printer, _) = printer("checksum");
+
+        assert_eq!(send(&mut printer, DATA, 0, &[0xFF; 16], true), (ALIVE, STATUS_CHECKSUM_ERROR));
+        assert!(printer.image.is_empty());
+        assert_eq!(send(&mut printer, STATUS, 0, &[], false), (ALIVE, 0x00));
+    }
+
+    #[test]
+    fn rle() {
+        let mut out = Vec::new();
+        decompress(&[0x02, 0x01, 0x02, 0x03, 0x81, 0xAA, 0x00, 0x04], &mut out);
+        assert_eq!(out, [0x01, 0x02, 0x03, 0xAA, 0xAA, 0xAA, 0x04]);
+
+        // Un paquete comprimido se descomprime en la imagen
+        let (mut printer, _) = printer("rle");
+        send(&mut printer, DATA, 1, &[0xFF, 0xAA, 0x01, 0x01, 0x02], false);
+        assert_eq!(printer.image.len(), 0x7F + 2 + 2);
+        assert!(printer.image[..0x7F + 2].iter().all(|&b| b == 0xAA));
+        assert_eq!(printer.image[0x7F + 2..], [0x01, 0x02]);
+    }
+
+    #[test]
+    fn print() {
+        let (mut printer, dir) = printer("print");
+
+        send(&mut printer, INIT, 0, &[], false);
+        assert_eq!(send(&mut printer, DATA, 0, &[0xFF; 2 * BYTES_PER_TILE_ROW], false), (ALIVE, STATUS_UNPROCESSED));
+        assert_eq!(send(&mut printer, DATA, 0, &[], false), (ALIVE, STATUS_UNPROCESSED | STATUS_READY));
+
+        // Una hoja, sin margen superior y con margen inferior
+        assert_eq!(send(&mut printer, PRINT, 0, &[1, 0x01, 0xE4, 0x40], false), (ALIVE, STATUS_PRINTING | STATUS_READY));
+
+        while printer.status & STATUS_PRINTING != 0 {
+            printer.update(0xFF, 0xFF, false);
+        }
+        assert_eq!(send(&mut printer, STATUS, 0, &[], false), (ALIVE, STATUS_READY));
+        assert_eq!(send(&mut printer, STATUS, 0, &[], false), (ALIVE, 0x00));
+
+        assert!(dir.join("print_0000.png").exists());
+        let _ = std::fs::remove_dir_all(dir);
+    }
+
+    #[test]
+    fn write_error() {
+        let (mut printer, dir) = printer("error");
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        send(&mut printer, DATA, 0, &[0xFF; 2 * BYTES_PER_TILE_ROW], false);
+        send(&mut printer, PRINT, 0, &[1, 0x01, 0xE4, 0x40], false);
+
+        assert!(printer.take_error().is_some());
+        assert!(printer.take_error().is_none());
+    }
+}
@@ -0,0 +1,150 @@
+use std::io::{self, Write};
+
+const SB: usize = 0xFF01;
+const SC: usize = 0xFF02;
+
+// Ciclos por bit con el reloj interno, 8192 Hz. El emulador es solo DMG, no hay reloj rapido de CGB
+const CYCLES_PER_BIT: u32 = 512;
+// Ciclos de una transferencia de 8 bits con el reloj interno normal
+pub const TRANSFER_CYCLES: u32 = 8 * CYCLES_PER_BIT;
+
+// Lo que esta conectado al otro lado del cable
+pub trait SerialDevice {
+    // Transferencia con reloj interno: la consola envia out y recibe el byte que devuelve.
+    // Se llama al empezar la transferencia, los bits se van desplazando despues en SB
+    fn transfer(&mut self, out: u8) -> u8;
+
+    // Se llama cada vez que avanza la consola. sb es el byte que se enviaria y waiting indica si hay
+    // una transferencia con reloj externo esperando. Devuelve el byte recibido cuando el otro extremo
+    // completa esa transferencia
+    fn update(&mut self, _cycles: u8, _sb: u8, _waiting: bool) -> Option<u8> {
+        None
+    }
+
+    // Error que ha ocurrido durante la emulacion y no se ha podido devolver, como al guardar una impresion.
+    // GameBoy lo recoge al final de cada frame
+    fn take_error(&mut self) -> Option<io::Error> {
+        None
+    }
+
+    // Termina lo que quede pendiente antes de desconectarlo, la impresora guarda la tira que estaba imprimiendo
+    fn finish(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+// Escribe en la consola los bytes enviados, para las ROMs de test que informan por el puerto serie
+pub struct SerialConsole;
+
+impl SerialDevice for SerialConsole {
+    fn transfer(&mut self, out: u8) -> u8 {
+        print!("{}", char::from(out));
+        let _ = std::io::stdout().flush();
+        0xFF
+    }
+}
+
+// Transferencia con reloj interno en curso
+struct Transfer {
+    incoming: u8,
+    bits_left: u8,
+    cycles: u32,
+}
+
+pub struct Serial {
+    sb: u8,
+    sc: u8,
+    transfer: Option<Transfer>,
+    device: Option<Box<dyn SerialDevice>>,
+}
+
+impl Default for Serial {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Serial {
+    pub fn new() -> Self {
+        Serial {
+            sb: 0x00,
+            sc: 0x00,
+            transfer: None,
+            device: None,
+        }
+    }
+
+    pub fn set_device(&mut self, device: Option<Box<dyn SerialDevice>>) -> Option<Box<dyn SerialDevice>> {
+        std::mem::replace(&mut self.device, device)
+    }
+
+    pub fn take_device_error(&mut self) -> Option<io::Error> {
+        self.device.as_mut().and_then(|device| device.take_error())
+    }
+
+    pub fn read(&self, dir: usize) -> u8 {
+        match dir {
+            SB => self.sb,
+            SC => self.sc | 0b01111110,
+            _ => unreachable!(),
+        }
+    }
+
+    pub fn write(&mut self, dir: usize, val: u8) {
+        match dir {
+            SB => self.sb = val,
+            SC => {
+                self.sc = val & 0b10000001;
+                self.transfer = None;
+
+                if self.sc & 0b10000001 == 0b10000001 {
+                    // Sin nada conectado se recibe 0xFF
+                    let incoming = self.device.as_mut().map_or(0xFF, |device| device.transfer(self.sb));
+
+                    self.transfer = Some(Transfer {
+                        incoming,
+                        bits_left: 8,
+                        cycles: 0,
+                    });
+                }
+            },
+            _ => unreachable!(),
+        }
+    }
+
+    // Devuelve true al completar una transferencia (interrupcion)
+    pub fn cycle(&mut self, cycles: u8) -> bool {
+        let waiting = self.sc & 0b10000001 == 0b10000000;
+
+        if let Some(device) = self.device.as_mut() {
+            if let Some(incoming) = device.update(cycles, self.sb, waiting) {
+                if waiting {
+                    self.sb = incoming;
+                    self.sc &= 0b01111111;
+                    return true;
+                }
+            }
+        }
+
+        let Some(transfer) = self.transfer.as_mut() else {
+            return false;
+        };
+
+        transfer.cycles += cycles as u32;
+        while transfer.cycles >= CYCLES_PER_BIT && transfer.bits_left > 0 {
+            transfer.cycles -= CYCLES_PER_BIT;
+            transfer.bits_left -= 1;
+
+            let bit = (transfer.incoming >> transfer.bits_left) & 0x01;
+            self.sb = (self.sb << 1) | bit;
+        }
+
+        if transfer.bits_left > 0 {
+            return false;
+        }
+
+        self.transfer = None;
+        self.sc &= 0b01111111;
+        true
+    }
+}
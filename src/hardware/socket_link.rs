@@ -0,0 +1,276 @@
+use std::{
+    io::{self, Read, Write},
+    net::{TcpListener, TcpStream, ToSocketAddrs},
+    sync::mpsc::{self, Receiver, TryRecvError},
+    thread,
+};
+
+use super::serial::{SerialDevice, TRANSFER_CYCLES};
+
+// Cada cuantos ciclos se envia la cuenta de ciclos al otro proceso
+const SYNC_INTERVAL: u64 = 4096;
+// Lo maximo que una consola puede adelantar a la otra. Tiene que ser mayor que SYNC_INTERVAL
+const MAX_AHEAD: u64 = 4 * SYNC_INTERVAL;
+
+// Mensajes del protocolo, un byte de tipo seguido de los datos
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Message {
+    // Ciclos ejecutados desde la conexion
+    Sync(u64),
+    // Transferencia con reloj interno del otro extremo y ciclo en el que empieza, espera un Reply
+    Transfer(u8, u64),
+    // Byte que devuelve el extremo esclavo, 0xFF si no estaba esperando
+    Reply(u8),
+}
+
+const SYNC: u8 = 0x01;
+const TRANSFER: u8 = 0x02;
+const REPLY: u8 = 0x03;
+
+impl Message {
+    fn write_to(&self, writer: &mut dyn Write) -> io::Result<()> {
+        match self {
+            Message::Sync(cycles) => {
+                writer.write_all(&[SYNC])?;
+                writer.write_all(&cycles.to_le_bytes())?;
+            },
+            Message::Transfer(val, cycles) => {
+                writer.write_all(&[TRANSFER, *val])?;
+                writer.write_all(&cycles.to_le_bytes())?;
+            },
+            Message::Reply(val) => writer.write_all(&[REPLY, *val])?,
+        }
+        writer.flush()
+    }
+
+    fn read_from(reader: &mut dyn Read) -> io::Result<Self> {
+        let mut kind = [0];
+        reader.read_exact(&mut kind)?;
+
+        match kind[0] {
+            SYNC => {
+                let mut cycles = [0; 8];
+                reader.read_exact(&mut cycles)?;
+                Ok(Message::Sync(u64::from_le_bytes(cycles)))
+            },
+            TRANSFER => {
+                let mut data = [0; 9];
+                reader.read_exact(&mut data)?;
+                let mut cycles = [0; 8];
+                cycles.copy_from_slice(&data[1..]);
+                Ok(Message::Transfer(data[0], u64::from_le_bytes(cycles)))
+            },
+            REPLY => {
+                let mut val = [0];
+                reader.read_exact(&mut val)?;
+                Ok(Message::Reply(val[0]))
+            },
+            kind => Err(io::Error::new(io::ErrorKind::InvalidData, format!("Mensaje desconocido: {:02X}", kind))),
+        }
+    }
+}
+
+// Cable entre dos procesos por un socket local. Cada extremo envia su cuenta de ciclos y se para
+// si adelanta al otro en mas de MAX_AHEAD ciclos
+pub struct SocketLink {
+    writer: Box<dyn Write>,
+    // Mensajes leidos por el hilo lector
+    messages: Receiver<Message>,
+    connected: bool,
+
+    cycles: u64,
+    remote_cycles: u64,
+    next_sync: u64,
+
+    sb: u8,
+    waiting: bool,
+    // Transferencia del otro extremo que se contesta cuando esta consola llegue al mismo ciclo
+    pending: Option<(u8, u64)>,
+    // Byte enviado por el otro extremo y ciclos hasta que termine de llegar
+    incoming: Option<(u8, u32)>,
+}
+
+impl SocketLink {
+    pub fn connect_tcp<A: ToSocketAddrs>(addr: A) -> io::Result<Self> {
+        let stream = TcpStream::connect(addr)?;
+        stream.set_nodelay(true)?;
+        Ok(SocketLink::from_streams(stream.try_clone()?, stream))
+    }
+
+    // Espera a que se conecte el otro proceso
+    pub fn listen_tcp<A: ToSocketAddrs>(addr: A) -> io::Result<Self> {
+        let (stream, _) = TcpListener::bind(addr)?.accept()?;
+        stream.set_nodelay(true)?;
+        Ok(SocketLink::from_streams(stream.try_clone()?, stream))
+    }
+
+    #[cfg(unix)]
+    pub fn connect_unix<P: AsRef<std::path::Path>>(path: P) -> io::Result<Self> {
+        let stream = std::os::unix::net::UnixStream::connect(path)?;
+        Ok(SocketLink::from_streams(stream.try_clone()?, stream))
+    }
+
+    // Crea el socket en path, borrando el que hubiera de una ejecucion anterior
+    #[cfg(unix)]
+    pub fn listen_unix<P: AsRef<std::path::Path>>(path: P) -> io::Result<Self> {
+        let _ = std::fs::remove_file(&path);
+        let (stream, _) = std::os::unix::net::UnixListener::bind(&path)?.accept()?;
+        Ok(SocketLink::from_streams(stream.try_clone()?, stream))
+    }
+
+    pub fn from_streams<R: Read + Send + 'static, W: Write + 'static>(mut reader: R, writer: W) -> Self {
+        let (sender, messages) = mpsc::channel();
+
+        // El hilo termina cuando se cierra la conexion o se destruye el SocketLink
+        thread::spawn(move || {
+            while let Ok(message) = Message::read_from(&mut reader) {
+                if sender.send(message).is_err() {
+                    break;
+                }
+            }
+        });
+
+        SocketLink {
+            writer: Box::new(writer),
+            messages,
+            connected: true,
+
+            cycles: 0,
+            remote_cycles: 0,
+            next_sync: 0,
+
+            sb: 0xFF,
+            waiting: false,
+            pending: None,
+            incoming: None,
+        }
+    }
+
+    pub fn connected(&self) -> bool {
+        self.connected
+    }
+
+    fn send(&mut self, message: Message) {
+        if self.connected && message.write_to(&mut self.writer).is_err() {
+            self.connected = false;
+        }
+    }
+
+    // Atiende un mensaje. Devuelve el byte si es la respuesta a una transferencia
+    fn handle(&mut self, message: Message) -> Option<u8> {
+        match message {
+            Message::Sync(cycles) => self.remote_cycles = cycles,
+            Message::Transfer(val, cycles) => {
+                self.remote_cycles = self.remote_cycles.max(cycles);
+                self.pending = Some((val, cycles));
+            },
+            Message::Reply(val) => return Some(val),
+        }
+        None
+    }
+
+    // Contesta la transferencia pendiente si esta consola ya ha llegado al ciclo en el que empezo.
+    // El otro extremo esta parado esperando la respuesta
+    fn answer_pending(&mut self, force: bool) {
+        let Some((val, cycles)) = self.pending else {
+            return;
+        };
+        if !force && self.cycles < cycles {
+            return;
+        }
+        self.pending = None;
+
+        // Solo se desplaza SB si hay una transferencia con reloj externo esperando
+        if self.waiting && self.incoming.is_none() {
+            self.incoming = Some((val, TRANSFER_CYCLES));
+            self.send(Message::Reply(self.sb));
+        } else {
+            self.send(Message::Reply(0xFF));
+        }
+    }
+
+    // Espera al siguiente mensaje, None si se ha cerrado la conexion
+    fn wait_message(&mut self) -> Option<Message> {
+        let message = self.messages.recv().ok();
+        self.connected = message.is_some();
+        message
+    }
+
+    fn process_pending(&mut self) {
+        loop {
+            match self.messages.try_recv() {
+                Ok(message) => {
+                    self.handle(message);
+                },
+                Err(TryRecvError::Empty) => return,
+                Err(TryRecvError::Disconnected) => {
+                    self.connected = false;
+                    return;
+                },
+            }
+        }
+    }
+}
+
+impl SerialDevice for SocketLink {
+    fn transfer(&mut self, out: u8) -> u8 {
+        if !self.connected {
+            return 0xFF;
+        }
+
+        self.send(Message::Transfer(out, self.cycles));
+
+        // Mientras se espera la respuesta se atienden los mensajes del otro extremo,
+        // que puede estar intentando transferir a la vez. Esta consola ya no espera con reloj externo
+        self.waiting = false;
+        while self.connected {
+            let Some(message) = self.wait_message() else {
+                break;
+            };
+
+            if let Some(val) = self.handle(message) {
+                return val;
+            }
+            self.answer_pending(true);
+        }
+
+        0xFF
+    }
+
+    fn update(&mut self, cycles: u8, sb: u8, waiting: bool) -> Option<u8> {
+        self.sb = sb;
+        self.waiting = waiting;
+        self.cycles += cycles as u64;
+
+        if self.connected {
+            if self.cycles >= self.next_sync {
+                self.next_sync = self.cycles + SYNC_INTERVAL;
+                self.send(Message::Sync(self.cycles));
+            }
+
+            self.process_pending();
+            self.answer_pending(false);
+
+            // Si se ha adelantado demasiado espera a que el otro extremo avance
+            if self.cycles > self.remote_cycles + MAX_AHEAD {
+                self.send(Message::Sync(self.cycles));
+
+                while self.connected && self.cycles > self.remote_cycles + MAX_AHEAD {
+                    if let Some(message) = self.wait_message() {
+                        self.handle(message);
+                        self.answer_pending(false);
+                    }
+                }
+            }
+        }
+
+        let (incoming, cycles_left) = self.incoming?;
+        if cycles_left > cycles as u32 {
+            self.incoming = Some((incoming, cycles_left - cycles as u32));
+            return None;
+        }
+
+        self.incoming = None;
+        Some(incoming)
+    }
+}
@@ -0,0 +1,262 @@
+const DIV: usize = 0xFF04;
+const TIMA: usize = 0xFF05;
+const TMA: usize = 0xFF06;
+const TAC: usize = 0xFF07;
+
+// Ciclos desde que TIMA se desborda hasta que se recarga con TMA (1 M-ciclo)
+const RELOAD_DELAY: u8 = 4;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum TimaState {
+    Running,
+    // TIMA se ha desbordado y vale 0, faltan n ciclos para recargarlo
+    Overflow(u8),
+    // Durante el M-ciclo de la recarga se ignoran las escrituras a TIMA
+    Reloading(u8),
+}
+
+pub struct Timer {
+    // Contador interno de 16 bits, DIV son sus 8 bits altos
+    counter: u16,
+    tima: u8,
+    tma: u8,
+    tac: u8,
+    state: TimaState,
+    // Bit del contador seleccionado por TAC AND timer activado, TIMA avanza en su flanco de bajada
+    last_and_result: bool,
+}
+
+impl Default for Timer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Timer {
+    pub fn new() -> Self {
+        Timer {
+            counter: 0,
+            tima: 0,
+            tma: 0,
+            tac: 0,
+            state: TimaState::Running,
+            last_and_result: false,
+        }
+    }
+
+    // Valor del contador interno al terminar el boot ROM
+    pub fn set_counter(&mut self, counter: u16) {
+        self.counter = counter;
+        self.last_and_result = self.and_result();
+    }
+
+    pub fn read(&self, dir: usize) -> u8 {
+        match dir {
+            DIV => (self.counter >> 8) as u8,
+            TIMA => self.tima,
+            TMA => self.tma,
+            TAC => self.tac | 0b11111000,
+            _ => unreachable!(),
+        }
+    }
+
+    // Devuelve true si la escritura provoca un evento DIV-APU
+    pub fn write(&mut self, dir: usize, val: u8) -> bool {
+        match dir {
+            DIV => {
+                // Poner el contador a 0 puede generar un flanco de bajada en TIMA y en el APU
+                let div_apu_event = self.counter & (1 << 12) != 0;
+                self.counter = 0;
+                self.detect_edge();
+                return div_apu_event;
+            },
+            TIMA => match self.state {
+                // Escribir antes de la recarga la cancela, y no hay interrupcion
+                TimaState::Overflow(_) => {
+                    self.tima = val;
+                    self.state = TimaState::Running;
+                },
+                TimaState::Reloading(_) => {},
+                TimaState::Running => self.tima = val,
+            },
+            TMA => {
+                self.tma = val;
+                // En el M-ciclo de la recarga el nuevo valor tambien llega a TIMA
+                if let TimaState::Reloading(_) = self.state {
+                    self.tima = val;
+                }
+            },
+            TAC => {
+                // Cambiar de bit o desactivar el timer tambien puede generar un flanco de bajada
+                self.tac = val & 0b00000111;
+                self.detect_edge();
+            },
+            _ => unreachable!(),
+        }
+
+        false
+    }
+
+    fn and_result(&self) -> bool {
+        let bit = match self.tac & 0b00000011 {
+            0b00 => 9,
+            0b01 => 3,
+            0b10 => 5,
+            _ => 7,
+        };
+
+        self.tac & 0b00000100 != 0 && self.counter & (1 << bit) != 0
+    }
+
+    fn detect_edge(&mut self) {
+        let and_result = self.and_result();
+
+        if self.last_and_result && !and_result {
+            self.increase_tima();
+        }
+        self.last_and_result = and_result;
+    }
+
+    fn increase_tima(&mut self) {
+        let (tima, overflow) = self.tima.overflowing_add(1);
+        self.tima = tima;
+
+        if overflow {
+            self.state = TimaState::Overflow(RELOAD_DELAY);
+        }
+    }
+
+    // (Timer, eventos DIV-APU)
+    pub fn cycle(&mut self, cycles: u8) -> (bool, u8) {
+        let mut int = false;
+        let mut div_apu_events = 0;
+
+        for _ in 0..cycles {
+            match self.state {
+                TimaState::Overflow(1) => {
+                    self.tima = self.tma;
+                    self.state = TimaState::Reloading(RELOAD_DELAY);
+                    int = true;
+                },
+                TimaState::Overflow(n) => self.state = TimaState::Overflow(n - 1),
+                TimaState::Reloading(1) => self.state = TimaState::Running,
+                TimaState::Reloading(n) => self.state = TimaState::Reloading(n - 1),
+                TimaState::Running => {},
+            }
+
+            let counter = self.counter;
+            self.counter = self.counter.wrapping_add(1);
+
+            // El secuenciador del APU avanza con el flanco de bajada del bit 4 de DIV
+            if counter & (1 << 12) != 0 && self.counter & (1 << 12) == 0 {
+                div_apu_events += 1;
+            }
+
+            self.detect_edge();
+        }
+
+        (int, div_apu_events)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // TIMA avanza cada 16 ciclos (bit 3 del contador), empezando en 0xFF para desbordar en el ciclo 16
+    fn overflowing_timer() -> Timer {
+        let mut timer = Timer::new();
+        timer.write(TMA, 0x42);
+        timer.write(TIMA, 0xFF);
+        timer.write(TAC, 0b101);
+        timer
+    }
+
+    // Avanza hasta el ciclo en el que TIMA se desborda
+    fn overflow(timer: &mut Timer) {
+        for _ in 0..16 {
+            assert!(!timer.cycle(1).0);
+        }
+        assert_eq!(timer.read(TIMA), 0x00);
+    }
+
+    #[test]
+    fn reload_delay() {
+        let mut timer = overflowing_timer();
+        overflow(&mut timer);
+
+        // TIMA se queda a 0 durante un M-ciclo y despues se recarga con la interrupcion
+        for _ in 0..3 {
+            assert!(!timer.cycle(1).0);
+            assert_eq!(timer.read(TIMA), 0x00);
+        }
+        assert!(timer.cycle(1).0);
+        assert_eq!(timer.read(TIMA), 0x42);
+    }
+
+    #[test]
+    fn write_tima_cancels_reload() {
+        let mut timer = overflowing_timer();
+        overflow(&mut timer);
+
+        timer.write(TIMA, 0x10);
+        for _ in 0..8 {
+            assert!(!timer.cycle(1).0);
+        }
+        assert_eq!(timer.read(TIMA), 0x10);
+    }
+
+    #[test]
+    fn write_tima_ignored_while_reloading() {
+        let mut timer = overflowing_timer();
+        overflow(&mut timer);
+        timer.cycle(4);
+
+        timer.write(TIMA, 0x10);
+        assert_eq!(timer.read(TIMA), 0x42);
+
+        // TMA si llega a TIMA en ese M-ciclo
+        timer.write(TMA, 0x20);
+        assert_eq!(timer.read(TIMA), 0x20);
+
+        // Pasado el M-ciclo de la recarga TIMA se puede escribir
+        timer.cycle(4);
+        timer.write(TIMA, 0x10);
+        assert_eq!(timer.read(TIMA), 0x10);
+    }
+
+    #[test]
+    fn div_reset_falling_edge() {
+        let mut timer = Timer::new();
+        timer.write(TAC, 0b101);
+        timer.set_counter(0b1000);
+
+        assert!(!timer.write(DIV, 0x00));
+        assert_eq!(timer.read(TIMA), 0x01);
+        assert_eq!(timer.read(DIV), 0x00);
+
+        // Con el bit 12 a 1 el reset tambien genera un evento DIV-APU
+        timer.set_counter(1 << 12);
+        assert!(timer.write(DIV, 0x00));
+    }
+
+    #[test]
+    fn tac_change_falling_edge() {
+        let mut timer = Timer::new();
+        timer.write(TAC, 0b101);
+        timer.set_counter(0b1000);
+
+        // Cambiar al bit 9, que esta a 0
+        timer.write(TAC, 0b100);
+        assert_eq!(timer.read(TIMA), 0x01);
+
+        // Desactivar el timer con el bit seleccionado a 1
+        timer.write(TAC, 0b101);
+        timer.write(TAC, 0b001);
+        assert_eq!(timer.read(TIMA), 0x02);
+
+        // Sin flanco de bajada no cambia
+        timer.write(TAC, 0b100);
+        assert_eq!(timer.read(TIMA), 0x02);
+    }
+}
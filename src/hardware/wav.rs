@@ -0,0 +1,88 @@
+use std::{fs::File, io::{self, BufWriter, Seek, SeekFrom, Write}, path::Path};
+
+const CHANNELS: u16 = 2;
+const BITS_PER_SAMPLE: u16 = 16;
+const HEADER_SIZE: u32 = 44;
+
+// Fichero WAV PCM de 16 bits estereo. Los tamaños de la cabecera se completan en finish()
+pub struct WavWriter<W: Write + Seek> {
+    writer: W,
+    sample_rate: u32,
+    // Bytes de audio escritos
+    data_len: u32,
+    finished: bool,
+}
+
+impl WavWriter<BufWriter<File>> {
+    pub fn create<P: AsRef<Path>>(path: P, sample_rate: u32) -> io::Result<Self> {
+        WavWriter::new(BufWriter::new(File::create(path)?), sample_rate)
+    }
+}
+
+impl<W: Write + Seek> WavWriter<W> {
+    pub fn new(writer: W, sample_rate: u32) -> io::Result<Self> {
+        let mut wav = WavWriter {
+            writer,
+            sample_rate,
+            data_len: 0,
+            finished: false,
+        };
+        wav.write_header()?;
+
+        Ok(wav)
+    }
+
+    fn write_header(&mut self) -> io::Result<()> {
+        let block_align = CHANNELS * BITS_PER_SAMPLE / 8;
+
+        self.writer.write_all(b"RIFF")?;
+        self.writer.write_all(&(HEADER_SIZE - 8 + self.data_len).to_le_bytes())?;
+        self.writer.write_all(b"WAVE")?;
+
+        self.writer.write_all(b"fmt ")?;
+        self.writer.write_all(&16u32.to_le_bytes())?;
+        // PCM
+        self.writer.write_all(&1u16.to_le_bytes())?;
+        self.writer.write_all(&CHANNELS.to_le_bytes())?;
+        self.writer.write_all(&self.sample_rate.to_le_bytes())?;
+        self.writer.write_all(&(self.sample_rate * block_align as u32).to_le_bytes())?;
+        self.writer.write_all(&block_align.to_le_bytes())?;
+        self.writer.write_all(&BITS_PER_SAMPLE.to_le_bytes())?;
+
+        self.writer.write_all(b"data")?;
+        self.writer.write_all(&self.data_len.to_le_bytes())
+    }
+
+    // Muestras estereo intercaladas (izquierda, derecha) en -1.0 - 1.0
+    pub fn write_samples(&mut self, samples: &[f32]) -> io::Result<()> {
+        for sample in samples {
+            let sample = (sample.clamp(-1.0, 1.0) * i16::MAX as f32) as i16;
+            self.writer.write_all(&sample.to_le_bytes())?;
+        }
+        self.data_len += samples.len() as u32 * 2;
+        self.finished = false;
+
+        Ok(())
+    }
+
+    // Reescribe la cabecera con el tamaño final. Tambien se llama al destruirlo, ignorando errores
+    pub fn finish(&mut self) -> io::Result<()> {
+        if self.finished {
+            return Ok(());
+        }
+
+        self.writer.seek(SeekFrom::Start(0))?;
+        self.write_header()?;
+        self.writer.seek(SeekFrom::End(0))?;
+        self.writer.flush()?;
+        self.finished = true;
+
+        Ok(())
+    }
+}
+
+impl<W: Write + Seek> Drop for WavWriter<W> {
+    fn drop(&mut self) {
+        let _ = self.finish();
+    }
+}
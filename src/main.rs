@@ -1,17 +1,54 @@
-mod hardware;
+use rust_gbc::hardware::{GameBoy, GameBoyEvent, bus::Bus, serial::SerialConsole, socket_link::SocketLink, printer::Printer, SerialDevice, Keys, SCREEN_WIDTH, SCREEN_HEIGHT};
+use sfml::{audio::SoundStreamPlayer, graphics::{RenderWindow, RenderTarget, Color, Image, Texture, Sprite, Transformable}, window::{Style, Event, Key}};
 
-use hardware::{GameBoy, bus::Bus, Keys};
-use sfml::{graphics::{RenderWindow, RenderTarget, Color}, window::{Style, Event, Key}};
+mod sfml_audio;
+
+const AUDIO_SAMPLE_RATE: u32 = 44100;
 
 fn main() {
+    // Solo puede haber una cosa conectada al puerto serie
+    let args: Vec<String> = std::env::args().collect();
+    let printer = args.iter().position(|arg| arg == "--printer");
+    if printer.is_some() && args.iter().any(|arg| arg == "--link-listen" || arg == "--link-connect") {
+        println!("--printer no se puede usar a la vez que --link-listen o --link-connect");
+        return;
+    }
+
+    // Impresora: --printer <directorio>
+    // Cable con otro proceso: --link-listen <dir> o --link-connect <dir>, con unix:<ruta> para sockets Unix
+    let mut serial_device: Option<Box<dyn SerialDevice>> = match printer {
+        Some(i) => match args.get(i + 1).map(Printer::new) {
+            Some(Ok(printer)) => Some(Box::new(printer)),
+            Some(Err(e)) => {
+                println!("No se ha podido crear el directorio de la impresora: {}", e);
+                None
+            },
+            None => {
+                println!("Falta el directorio de la impresora");
+                None
+            },
+        },
+        None => match open_link() {
+            Ok(link) => link.map(|link| Box::new(link) as Box<dyn SerialDevice>),
+            Err(e) => {
+                println!("No se ha podido conectar el cable: {}", e);
+                None
+            },
+        },
+    };
+
     let mut window = RenderWindow::new(
-        (160 * 2, 144 * 2),
+        (SCREEN_WIDTH as u32 * 2, SCREEN_HEIGHT as u32 * 2),
         "GameBoy",
         Style::CLOSE,
         &Default::default(),
     );
     window.set_framerate_limit(60);
 
+    let (mut audio_stream, audio_sink) = sfml_audio::new(AUDIO_SAMPLE_RATE);
+    let mut audio_player = SoundStreamPlayer::new(&mut audio_stream);
+    audio_player.play();
+
     let roms = [
         //"roms/individual/01-special.gb",
         //"roms/individual/02-interrupts.gb",
@@ -36,7 +73,24 @@ fn main() {
     for i in roms {
         let mut gameboy = GameBoy::new(Bus::new(), true);
         gameboy.reset();
-        gameboy.load_rom(i);
+        if let Err(e) = gameboy.set_audio_sink(Box::new(audio_sink.clone())) {
+            println!("Sin audio: {}", e);
+        }
+        // Las ROMs de test escriben los resultados por el puerto serie
+        match serial_device.take() {
+            Some(device) => gameboy.set_serial_device(device),
+            None => gameboy.set_serial_device(Box::new(SerialConsole)),
+        }
+        if let Err(e) = gameboy.load_rom(i) {
+            println!("No se ha podido cargar {}: {}", i, e);
+            continue;
+        }
+
+        if let Some(header) = gameboy.header() {
+            for warning in &header.warnings {
+                println!("Aviso: {}", warning);
+            }
+        }
     
         'inner: loop {
             while let Some(event) = window.poll_event() {
@@ -73,13 +127,60 @@ fn main() {
     
             gameboy.cycle();
 
+            while let Some(event) = gameboy.poll_event() {
+                match event {
+                    // SFML no tiene soporte para vibracion
+                    GameBoyEvent::Rumble(_) => {},
+                    GameBoyEvent::SaveFailed(e) => println!("No se ha podido guardar la partida: {}", e),
+                    GameBoyEvent::SerialDeviceFailed(e) => println!("Error en el puerto serie: {}", e),
+                }
+            }
+
             window.clear(Color::BLACK);
             
-            gameboy.draw(&mut window);
+            draw(&gameboy, &mut window);
             
             //println!("{}", gameboy.cpu.bus.read(0xFF00));
 
             window.display()
         }
+
+        if let Err(e) = gameboy.save_ram() {
+            println!("No se ha podido guardar la partida: {}", e);
+        }
+
+        // La impresora guarda la tira que estuviera imprimiendo
+        if let Some(mut device) = gameboy.take_serial_device() {
+            if let Err(e) = device.finish() {
+                println!("Error en el puerto serie: {}", e);
+            }
+        }
     }
-}
\ No newline at end of file
+}
+
+fn open_link() -> std::io::Result<Option<SocketLink>> {
+    let args: Vec<String> = std::env::args().collect();
+
+    let Some(i) = args.iter().position(|arg| arg == "--link-listen" || arg == "--link-connect") else {
+        return Ok(None);
+    };
+    let Some(addr) = args.get(i + 1) else {
+        return Err(std::io::Error::new(std::io::ErrorKind::InvalidInput, "Falta la direccion"));
+    };
+    let listen = args[i] == "--link-listen";
+
+    #[cfg(unix)]
+    if let Some(path) = addr.strip_prefix("unix:") {
+        return if listen { SocketLink::listen_unix(path) } else { SocketLink::connect_unix(path) }.map(Some);
+    }
+
+    if listen { SocketLink::listen_tcp(addr) } else { SocketLink::connect_tcp(addr) }.map(Some)
+}
+
+fn draw(gameboy: &GameBoy, window: &mut RenderWindow) {
+    let image = Image::create_from_pixels(SCREEN_WIDTH as u32, SCREEN_HEIGHT as u32, gameboy.frame_buffer()).unwrap();
+    let texture = Texture::from_image(&image).unwrap();
+    let mut sprite = Sprite::with_texture(&texture);
+    sprite.set_scale((2.0, 2.0));
+    window.draw(&sprite);
+}
@@ -0,0 +1,96 @@
+use std::{collections::VecDeque, sync::{Arc, Mutex}};
+
+use rust_gbc::hardware::AudioSink;
+use sfml::{audio::SoundStream, system::Time};
+
+// Muestras (i16, estereo intercaladas) que SFML pide de cada vez
+const CHUNK_SAMPLES: usize = 1024;
+// Nivel del buffer que se intenta mantener, en muestras estereo intercaladas (~60 ms a 44.1 kHz)
+const TARGET_BUFFERED: usize = 2 * 2646;
+// A partir de aqui se descartan las muestras mas antiguas
+const MAX_BUFFERED: usize = TARGET_BUFFERED * 4;
+// Maxima correccion de la frecuencia de salida (0.5%), no se nota en el tono
+const MAX_RATE_ADJUSTMENT: f64 = 0.005;
+
+type SharedBuffer = Arc<Mutex<VecDeque<i16>>>;
+
+// Lado de SFML, se ejecuta en su hilo de audio
+pub struct SfmlAudioStream {
+    buffer: SharedBuffer,
+    chunk: Vec<i16>,
+    sample_rate: u32,
+    // Ultima muestra reproducida, se repite si no hay datos para no generar chasquidos
+    last: (i16, i16),
+}
+
+// Lado del emulador
+#[derive(Clone)]
+pub struct SfmlAudioSink {
+    buffer: SharedBuffer,
+    sample_rate: u32,
+}
+
+pub fn new(sample_rate: u32) -> (SfmlAudioStream, SfmlAudioSink) {
+    let buffer = Arc::new(Mutex::new(VecDeque::with_capacity(MAX_BUFFERED)));
+
+    let stream = SfmlAudioStream {
+        buffer: buffer.clone(),
+        chunk: vec![0; CHUNK_SAMPLES],
+        sample_rate,
+        last: (0, 0),
+    };
+    let sink = SfmlAudioSink { buffer, sample_rate };
+
+    (stream, sink)
+}
+
+impl SoundStream for SfmlAudioStream {
+    fn get_data(&mut self) -> (&mut [i16], bool) {
+        let mut buffer = self.buffer.lock().unwrap();
+
+        for pair in self.chunk.chunks_exact_mut(2) {
+            if buffer.len() >= 2 {
+                self.last = (buffer.pop_front().unwrap(), buffer.pop_front().unwrap());
+            }
+            pair[0] = self.last.0;
+            pair[1] = self.last.1;
+        }
+
+        (&mut self.chunk, true)
+    }
+
+    fn seek(&mut self, _offset: Time) {}
+
+    fn channel_count(&self) -> u32 {
+        2
+    }
+
+    fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+}
+
+impl AudioSink for SfmlAudioSink {
+    fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
+    fn push_samples(&mut self, samples: &[f32]) {
+        let mut buffer = self.buffer.lock().unwrap();
+
+        buffer.extend(samples.iter().map(|s| (s.clamp(-1.0, 1.0) * i16::MAX as f32) as i16));
+
+        if buffer.len() > MAX_BUFFERED {
+            let excess = (buffer.len() - TARGET_BUFFERED) & !1;
+            buffer.drain(..excess);
+        }
+    }
+
+    // Si el buffer esta por encima del objetivo se generan menos muestras y al reves
+    fn rate_adjustment(&self) -> f64 {
+        let buffered = self.buffer.lock().unwrap().len() as f64;
+        let error = (TARGET_BUFFERED as f64 - buffered) / TARGET_BUFFERED as f64;
+
+        1.0 + error.clamp(-1.0, 1.0) * MAX_RATE_ADJUSTMENT
+    }
+}